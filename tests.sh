@@ -13,31 +13,60 @@ if [ ! -d "vfs" ]; then
   exit 1
 fi
 
-# Iniciar servidor desde carpeta vfs
-echo "🚀 Iniciando servidor VFS en segundo plano desde ./vfs..."
-cd vfs
-cargo run +nightly 9001 > ../server.log 2>&1 &
-SERVER_PID=$!
-cd ..
-
-echo "🔧 PID del servidor: $SERVER_PID"
-
-# Esperar a que el servidor esté disponible
-echo "⏳ Esperando a que el servidor esté listo en http://localhost:9001..."
-for i in {1..15}; do
-  if curl -s http://localhost:9001 >/dev/null; then
-    echo "✅ Servidor disponible. Ejecutando tests..."
-    break
-  fi
-  sleep 3
-  if [ "$i" -eq 15 ]; then
-    echo "❌ El servidor no respondió a tiempo. Abortando."
-    kill $SERVER_PID || true
-    exit 1
-  fi
-done
-
 API_URL="http://localhost:9001"  # Cambia si tu API corre en otro host/puerto
+ROOT_DIR=$(pwd)
+
+# El servidor se ejecuta en un directorio temporal, donde crea data/ y state/. Así cada ejecución empieza
+# sin datos ni WAL de ejecuciones anteriores y no modifica los archivos de vfs/.
+RUN_DIR=$(mktemp -d)
+mkdir -p "$RUN_DIR/data" "$RUN_DIR/state"
+trap 'rm -rf "$RUN_DIR"' EXIT
+
+# Inicia el servidor en RUN_DIR y espera a que responda.
+# Los datos quedan en RUN_DIR/data y RUN_DIR/state, así que sobreviven a los reinicios.
+function start_server() {
+  echo "🚀 Iniciando servidor VFS en segundo plano en $RUN_DIR..."
+  cd "$RUN_DIR"
+  cargo run --manifest-path "$ROOT_DIR/vfs/Cargo.toml" +nightly 9001 >> "$ROOT_DIR/server.log" 2>&1 &
+  SERVER_PID=$!
+  cd "$ROOT_DIR"
+
+  echo "🔧 PID del servidor: $SERVER_PID"
+
+  # Esperar a que el servidor esté disponible
+  echo "⏳ Esperando a que el servidor esté listo en $API_URL..."
+  for i in {1..15}; do
+    if curl -s $API_URL >/dev/null; then
+      echo "✅ Servidor disponible. Ejecutando tests..."
+      break
+    fi
+    sleep 3
+    if [ "$i" -eq 15 ]; then
+      echo "❌ El servidor no respondió a tiempo. Abortando."
+      kill $SERVER_PID || true
+      exit 1
+    fi
+  done
+}
+
+# Simula una caída: mata el servidor con SIGKILL, sin darle ocasión de volcar la memtable ni guardar el estado.
+function crash_server() {
+  echo -e "\n💥 Matando el servidor (kill -9)..."
+  kill -9 $SERVER_PID
+  wait $SERVER_PID 2>/dev/null || true
+}
+
+# Mata el servidor, borra sus datos y lo vuelve a arrancar sin inicializar, para los tests que necesitan
+# un VFSManager nuevo (otra dimensión u otras opciones en /init).
+function reset_server() {
+  crash_server
+  rm -rf "$RUN_DIR/data" "$RUN_DIR/state"
+  mkdir -p "$RUN_DIR/data" "$RUN_DIR/state"
+  start_server
+}
+
+: > server.log
+start_server
 
 # Función para ejecutar una solicitud y validar código de estado esperado
 function test_endpoint() {
@@ -56,7 +85,12 @@ function test_endpoint() {
     response=$(curl -s -w "%{http_code}" -o tmp_response.json -X "$method" "$API_URL$endpoint" -H "Content-Type: application/json" -d "$data")
   fi
 
-  cat tmp_response.json | jq .
+  # Las respuestas grandes (vectores de dimensión alta) se muestran en una línea recortada.
+  if [ "$(wc -c < tmp_response.json)" -gt 4096 ]; then
+    jq -c . tmp_response.json | cut -c1-300
+  else
+    cat tmp_response.json | jq .
+  fi
 
   if [ "$response" -ne "$expected_status" ]; then
     echo "❌ Error: Se esperaba código HTTP $expected_status pero se recibió $response"
@@ -69,6 +103,91 @@ function test_endpoint() {
   fi
 }
 
+# Comprueba el contenido de la última respuesta (tmp_response.json) con una expresión jq.
+# Los argumentos que siguen al nombre del test se pasan a jq (por ejemplo --argjson).
+function check_response() {
+  local filter=$1
+  local test_name=$2
+  shift 2
+
+  if jq -e "$@" "$filter" tmp_response.json >/dev/null; then
+    echo "✅ Éxito: $test_name"
+  else
+    echo "❌ Error: $test_name (no se cumple $filter)"
+    kill -9 $SERVER_PID
+    exit 1
+  fi
+}
+
+# Inserta `count` vectores de dimensión `dim` en una colección sin mostrar cada respuesta.
+# Los valores (entre 0 y 1) se derivan del número de vector, así que dos llamadas iguales insertan los mismos vectores.
+function insert_vectors() {
+  local collection=$1
+  local count=$2
+  local dim=$3
+
+  echo -e "\n===> Insertando $count vectores de dimensión $dim en $collection"
+  for i in $(seq 1 $count); do
+    local body
+    body=$(jq -nc --argjson i $i --argjson d $dim '{values: [range($d) | ($i * 12.9898 + . * 78.233) | sin * 43758.5453 | . - floor], name: "Vector \($i)", tags: ["generado"]}')
+    local status
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST "$API_URL/collections/$collection/vectors" -H "Content-Type: application/json" -d "$body")
+    if [ "$status" -ne 201 ]; then
+      echo "❌ Error: No se pudo insertar el vector $i en $collection (código HTTP $status)"
+      kill -9 $SERVER_PID
+      exit 1
+    fi
+  done
+  echo "✅ Éxito: $count vectores insertados"
+}
+
+# Borra los vectores con ids entre `first` y `last` de una colección sin mostrar cada respuesta.
+function delete_vectors() {
+  local collection=$1
+  local first=$2
+  local last=$3
+
+  echo -e "\n===> Borrando los vectores $first a $last de $collection"
+  for id in $(seq $first $last); do
+    local status
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE "$API_URL/collections/$collection/vectors/$id")
+    if [ "$status" -ne 200 ]; then
+      echo "❌ Error: No se pudo borrar el vector $id de $collection (código HTTP $status)"
+      kill -9 $SERVER_PID
+      exit 1
+    fi
+  done
+  echo "✅ Éxito: $((last - first + 1)) vectores borrados"
+}
+
+# Cuerpo de una búsqueda aproximada con un vector generado igual que los de `insert_vectors`.
+function query_body() {
+  local i=$1
+  local dim=$2
+  local top_k=$3
+  jq -nc --argjson i $i --argjson d $dim --argjson k $top_k '{values: [range($d) | ($i * 12.9898 + . * 78.233) | sin * 43758.5453 | . - floor], top_k: $k, ef_search: $k, search_type: "approximate"}'
+}
+
+# Búsqueda en una colección sin mostrar la respuesta. Imprime los ids y las distancias en una línea.
+function search_quiet() {
+  local collection=$1
+  local body=$2
+
+  local status
+  status=$(curl -s -o tmp_response.json -w "%{http_code}" -X POST "$API_URL/collections/$collection/search" -H "Content-Type: application/json" -d "$body")
+  if [ "$status" -ne 200 ]; then
+    echo "❌ Error: La búsqueda en $collection falló (código HTTP $status)" >&2
+    kill -9 $SERVER_PID
+    exit 1
+  fi
+  search_results
+}
+
+# Ids y distancias de la última búsqueda en una línea, para comparar resultados.
+function search_results() {
+  jq -c '[.results[] | [.id, .distance]]' tmp_response.json
+}
+
 # 1. Inicializar
 test_endpoint POST "/init" '{
   "vector_dimension": 4,
@@ -112,6 +231,626 @@ test_endpoint POST "/snapshot" '' 200 "7. Guardar estado (snapshot)" $SERVER_PID
 # 8. Restaurar snapshot
 test_endpoint POST "/restore" '' 200 "8. Restaurar estado (snapshot)" $SERVER_PID
 
+# 9. Actualizar vector (upsert)
+test_endpoint PUT "/vectors/1" '{
+  "values": [4.0, 3.0, 2.0, 1.0],
+  "name": "Vector actualizado",
+  "tags": ["demo", "updated"]
+}' 200 "9. Actualizar vector con ID 1" $SERVER_PID
+
+# 9. Un id que nunca se ha asignado no se puede crear con upsert
+test_endpoint PUT "/vectors/18446744073709551615" '{
+  "values": [4.0, 3.0, 2.0, 1.0],
+  "name": "Vector con id no asignado",
+  "tags": ["demo"]
+}' 404 "9. Rechazar upsert de un id no asignado" $SERVER_PID
+
+# 10. Borrar vector
+test_endpoint DELETE "/vectors/1" '' 200 "10. Borrar vector con ID 1" $SERVER_PID
+
+# 11. El vector borrado ya no existe
+test_endpoint GET "/vectors/1" "" 404 "11. Obtener vector borrado con ID 1" $SERVER_PID
+
+# 12. Compactar el archivo de datos
+test_endpoint POST "/compact" '' 200 "12. Compactar archivo de datos" $SERVER_PID
+
+# 13. Estadísticas de la caché de lectura
+test_endpoint GET "/stats" "" 200 "13. Obtener estadísticas" $SERVER_PID
+
+# 14. Crear una colección con otra dimensión
+test_endpoint POST "/collections" '{
+  "name": "test_collection",
+  "vector_dimension": 2,
+  "distance_method": "cosine",
+  "hnsw": { "m": 8, "ef_construction": 100, "keep_pruned": true }
+}' 201 "14. Crear colección" $SERVER_PID
+
+# 15. Insertar vector en la colección
+test_endpoint POST "/collections/test_collection/vectors" '{
+  "values": [1.0, 0.0],
+  "name": "Vector de la colección",
+  "tags": ["collection"]
+}' 201 "15. Insertar vector en la colección" $SERVER_PID
+
+# 16. Listar colecciones
+test_endpoint GET "/collections" "" 200 "16. Listar colecciones" $SERVER_PID
+
+# 17. Borrar la colección
+test_endpoint DELETE "/collections/test_collection" '' 200 "17. Borrar colección" $SERVER_PID
+
+# 18. Parámetros HNSW inválidos
+test_endpoint POST "/collections" '{
+  "name": "invalid_hnsw",
+  "vector_dimension": 2,
+  "hnsw": { "m": 1 }
+}' 400 "18. Rechazar parámetros HNSW inválidos" $SERVER_PID
+
+# 19. Búsqueda con el índice IVF
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "nprobe": 2,
+  "search_type": "ivf",
+  "distance_method": "euclidean"
+}' 200 "19. Buscar con el índice IVF" $SERVER_PID
+
+# 20. nprobe inválido
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "nprobe": 0,
+  "search_type": "ivf"
+}' 400 "20. Rechazar nprobe inválido" $SERVER_PID
+
+# 21. Búsqueda con el índice IVF-PQ y reordenación exacta
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "rerank": 10,
+  "search_type": "ivf_pq",
+  "distance_method": "euclidean"
+}' 200 "21. Buscar con el índice IVF-PQ" $SERVER_PID
+
+# 22. pq_m que no divide la dimensión
+test_endpoint POST "/collections" '{
+  "name": "invalid_pq",
+  "vector_dimension": 4,
+  "ivf": { "pq_m": 3 }
+}' 400 "22. Rechazar parámetros IVF-PQ inválidos" $SERVER_PID
+
+# 23. Búsqueda LSH con distancia coseno
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "probes": 8,
+  "search_type": "lsh",
+  "distance_method": "cosine"
+}' 200 "23. Buscar con el índice LSH" $SERVER_PID
+
+# 24. LSH con distancia euclídea
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "search_type": "lsh",
+  "distance_method": "euclidean"
+}' 400 "24. Rechazar LSH sin distancia coseno" $SERVER_PID
+
+# 25. Búsqueda con el grafo Vamana
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "ef_search": 16,
+  "search_type": "vamana",
+  "distance_method": "euclidean"
+}' 200 "25. Buscar con el grafo Vamana" $SERVER_PID
+
+# 26. Grado Vamana inválido
+test_endpoint POST "/collections" '{
+  "name": "invalid_vamana",
+  "vector_dimension": 4,
+  "vamana": { "degree": 0 }
+}' 400 "26. Rechazar parámetros Vamana inválidos" $SERVER_PID
+
+# 27. Búsqueda por producto escalar
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "search_type": "exact",
+  "distance_method": "dot"
+}' 200 "27. Buscar por producto escalar" $SERVER_PID
+
+# 28. Colección de huellas binarias con distancia Hamming
+test_endpoint POST "/collections" '{
+  "name": "fingerprints",
+  "vector_dimension": 4,
+  "distance_method": "hamming"
+}' 201 "28. Crear colección con distancia Hamming" $SERVER_PID
+
+# 29. Vector no binario en una colección Hamming
+test_endpoint POST "/collections/fingerprints/vectors" '{
+  "values": [1.0, 0.0, 0.5, 1.0],
+  "name": "Huella inválida",
+  "tags": ["error"]
+}' 400 "29. Rechazar vector no binario con distancia Hamming" $SERVER_PID
+
+# 30. Borrar la colección Hamming
+test_endpoint DELETE "/collections/fingerprints" '' 200 "30. Borrar colección Hamming" $SERVER_PID
+
+# 31. Búsqueda con distancia Minkowski
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "search_type": "exact",
+  "distance_method": "minkowski",
+  "p": 3
+}' 200 "31. Buscar con distancia Minkowski" $SERVER_PID
+
+# 32. Minkowski sin exponente
+test_endpoint POST "/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "distance_method": "minkowski"
+}' 400 "32. Rechazar Minkowski sin p" $SERVER_PID
+
+# 33. Colección con una dimensión que no coincide con los carriles SIMD
+test_endpoint POST "/collections" '{
+  "name": "simd_any_dim",
+  "vector_dimension": 3,
+  "distance_method": "simd_cosine"
+}' 201 "33. Crear colección SIMD de dimensión 3" $SERVER_PID
+
+# 34. Insertar vector en la colección SIMD
+test_endpoint POST "/collections/simd_any_dim/vectors" '{
+  "values": [1.0, 2.0, 3.0],
+  "name": "Vector de dimensión 3",
+  "tags": ["simd"]
+}' 201 "34. Insertar vector de dimensión 3" $SERVER_PID
+
+# 35. Búsqueda SIMD con dimensión 3
+test_endpoint POST "/collections/simd_any_dim/search" '{
+  "values": [1.0, 2.0, 2.0],
+  "top_k": 1,
+  "search_type": "exact"
+}' 200 "35. Buscar con SIMD en dimensión 3" $SERVER_PID
+
+# 36. Borrar la colección SIMD
+test_endpoint DELETE "/collections/simd_any_dim" '' 200 "36. Borrar colección SIMD" $SERVER_PID
+
+# 37. Insertar un vector sin volcar la memtable
+test_endpoint POST "/vectors" '{
+  "values": [1.0, 0.0, -1.0, 0.0],
+  "name": "Vector en el WAL",
+  "tags": ["wal"]
+}' 201 "37. Insertar vector sin flush" $SERVER_PID
+WAL_ID=$(jq .id tmp_response.json)
+
+# 38. Tras una caída, el vector se recupera del WAL antes de aceptar peticiones
+crash_server
+start_server
+test_endpoint GET "/vectors/$WAL_ID" "" 200 "38. Obtener el vector tras la caída" $SERVER_PID
+check_response '.values == [1.0, 0.0, -1.0, 0.0] and .name == "Vector en el WAL"' "38. El vector recuperado del WAL está completo"
+
+# 39. Insertar tres vectores y volcarlos al archivo de datos
+for i in 1 2 3; do
+  test_endpoint POST "/vectors" "{
+    \"values\": [0.0, 1.0, 0.0, -1.0],
+    \"name\": \"Vector $i\",
+    \"tags\": [\"recovery\"]
+  }" 201 "39. Insertar vector $i de 3" $SERVER_PID
+done
+LAST_ID=$(jq .id tmp_response.json)
+test_endpoint POST "/flush" '' 200 "39. Volcar la memtable" $SERVER_PID
+
+# 40. Perder el snapshot del índice y dejar basura al final del archivo de datos (una escritura a medias)
+crash_server
+rm -f "$RUN_DIR/state/my_vfs/vfs_state.bin"
+printf 'registro a medias' >> "$RUN_DIR/data/my_vfs/vectors.dat"
+start_server
+
+# 41. Los vectores se recuperan recorriendo el archivo de datos
+for id in $(seq $((LAST_ID - 2)) $LAST_ID); do
+  test_endpoint GET "/vectors/$id" "" 200 "41. Obtener el vector $id tras la recuperación" $SERVER_PID
+done
+check_response '.values == [0.0, 1.0, 0.0, -1.0]' "41. El último vector conserva sus valores"
+
+# 42. El siguiente vector recibe un id nuevo
+test_endpoint POST "/vectors" '{
+  "values": [0.0, 0.0, 1.0, 0.0],
+  "name": "Vector nuevo",
+  "tags": ["recovery"]
+}' 201 "42. Insertar vector tras la recuperación" $SERVER_PID
+check_response '.id > $last' "42. El nuevo vector no reutiliza ningún id" --argjson last "$LAST_ID"
+LAST_ID=$(jq .id tmp_response.json)
+
+# 43. Borrar el vector con el id más alto y compactar: el archivo compactado conserva su lápida
+test_endpoint DELETE "/vectors/$LAST_ID" '' 200 "43. Borrar el vector con el id más alto" $SERVER_PID
+test_endpoint POST "/flush" '' 200 "43. Volcar la memtable" $SERVER_PID
+test_endpoint POST "/compact" '' 200 "43. Compactar el archivo de datos" $SERVER_PID
+
+# 44. Perder el snapshot y el WAL y recuperar el índice desde el archivo compactado
+crash_server
+rm -f "$RUN_DIR/state/my_vfs/vfs_state.bin" "$RUN_DIR/state/my_vfs/vfs.wal"
+start_server
+test_endpoint POST "/init" '{
+  "vector_dimension": 4,
+  "storage_name": "my_vfs",
+  "truncate_data": false,
+  "quantize": true
+}' 200 "44. Recuperar el índice desde el archivo compactado" $SERVER_PID
+
+# 45. Los vectores vivos siguen disponibles y el id borrado no se reutiliza
+test_endpoint GET "/vectors/$((LAST_ID - 1))" "" 200 "45. Obtener un vector tras la recuperación" $SERVER_PID
+test_endpoint POST "/vectors" '{
+  "values": [0.0, 0.0, 0.0, 1.0],
+  "name": "Vector tras compactar",
+  "tags": ["recovery"]
+}' 201 "45. Insertar vector tras la recuperación" $SERVER_PID
+check_response '.id > $last' "45. El nuevo vector no reutiliza el id $LAST_ID" --argjson last "$LAST_ID"
+
+# 46. VFSManager nuevo para comprobar los checksums del archivo de datos
+reset_server
+test_endpoint POST "/init" '{
+  "vector_dimension": 2,
+  "storage_name": "corrupted",
+  "truncate_data": true,
+  "quantize": false
+}' 200 "46. Inicializar VFSManager para los checksums" $SERVER_PID
+
+# 47. Insertar tres vectores y volcarlos al archivo de datos
+for i in 1 2 3; do
+  test_endpoint POST "/vectors" "{
+    \"values\": [$i.0, 0.0],
+    \"name\": \"Vector $i\",
+    \"tags\": [\"crc\"]
+  }" 201 "47. Insertar vector $i de 3" $SERVER_PID
+done
+test_endpoint POST "/flush" '' 200 "47. Volcar la memtable" $SERVER_PID
+
+# 48. Cambiar un byte de los datos del segundo registro.
+# Cabecera del archivo (8 bytes) y de cada registro: marca (4), tamaño u32 LE (4) y CRC32 (4).
+crash_server
+DATA_FILE="$RUN_DIR/data/corrupted/vectors.dat"
+FIRST_LEN=$(od -An -tu4 -j12 -N4 "$DATA_FILE" | tr -d ' ')
+SECOND_DATA=$((8 + 12 + FIRST_LEN + 12))
+printf '\xff' | dd of="$DATA_FILE" bs=1 seek=$((SECOND_DATA + 2)) conv=notrunc status=none
+start_server
+
+# 49. El registro dañado se rechaza al leerlo, los demás se siguen leyendo
+test_endpoint GET "/vectors/2" "" 404 "49. Rechazar el vector con checksum incorrecto" $SERVER_PID
+test_endpoint GET "/vectors/3" "" 200 "49. Obtener el vector siguiente al dañado" $SERVER_PID
+
+# 50. Sin snapshot, la recuperación salta el registro dañado
+crash_server
+rm -f "$RUN_DIR/state/corrupted/vfs_state.bin"
+start_server
+test_endpoint GET "/vectors/1" "" 200 "50. Obtener el vector anterior al dañado" $SERVER_PID
+test_endpoint GET "/vectors/2" "" 404 "50. El vector dañado no se recupera" $SERVER_PID
+test_endpoint GET "/vectors/3" "" 200 "50. Obtener el vector siguiente al dañado" $SERVER_PID
+check_response '.values == [3.0, 0.0]' "50. El vector siguiente al dañado conserva sus valores"
+
+# 51. Un archivo sin la cabecera "VFSD" no se abre
+crash_server
+printf 'XXXX' | dd of="$DATA_FILE" bs=1 seek=0 conv=notrunc status=none
+rm -f "$RUN_DIR/state/corrupted/vfs_state.bin" "$RUN_DIR/state/corrupted/vfs.wal"
+start_server
+test_endpoint POST "/init" '{
+  "vector_dimension": 2,
+  "storage_name": "corrupted",
+  "truncate_data": false,
+  "quantize": false
+}' 500 "51. Rechazar un archivo de datos sin cabecera" $SERVER_PID
+check_response '.error | contains("is not a VFS data file")' "51. El error indica que el archivo no es de VFS"
+
+# 52. VFSManager con vectores más grandes que el buffer de lectura (8 KB): 2500 f32 son 10 KB por registro
+reset_server
+test_endpoint POST "/init" '{
+  "vector_dimension": 2500,
+  "storage_name": "large_dim",
+  "truncate_data": true,
+  "quantize": false
+}' 200 "52. Inicializar VFSManager de dimensión 2500" $SERVER_PID
+
+# 53. Insertar dos vectores grandes y volcarlos al archivo de datos
+LARGE_A=$(jq -nc '[range(2500) | . / 2500]')
+LARGE_B=$(jq -nc '[range(2500) | 1 - . / 2500]')
+test_endpoint POST "/vectors" "$(jq -nc --argjson v "$LARGE_A" '{values: $v, name: "Vector grande A", tags: ["large"]}')" 201 "53. Insertar vector grande A" $SERVER_PID
+LARGE_ID=$(jq .id tmp_response.json)
+test_endpoint POST "/vectors" "$(jq -nc --argjson v "$LARGE_B" '{values: $v, name: "Vector grande B", tags: ["large"]}')" 201 "53. Insertar vector grande B" $SERVER_PID
+test_endpoint POST "/flush" '' 200 "53. Volcar la memtable" $SERVER_PID
+
+# 54. Leer el vector grande del archivo de datos
+test_endpoint GET "/vectors/$LARGE_ID" "" 200 "54. Obtener vector grande" $SERVER_PID
+check_response '.values == $v' "54. El vector grande conserva sus 2500 valores" --argjson v "$LARGE_A"
+
+# 55. Búsqueda exacta y aproximada con el vector grande
+for search_type in exact approximate; do
+  test_endpoint POST "/search" "$(jq -nc --argjson v "$LARGE_A" --arg t "$search_type" '{values: $v, top_k: 2, search_type: $t, distance_method: "euclidean"}')" 200 "55. Búsqueda $search_type con vectores grandes" $SERVER_PID
+  check_response '.results | length == 2 and .[0].id == $id and .[0].distance < 0.001' "55. El vector más cercano en la búsqueda $search_type es el propio vector" --argjson id "$LARGE_ID"
+done
+
+# 56. VFSManager que lee el archivo de datos con mmap
+reset_server
+test_endpoint POST "/init" '{
+  "vector_dimension": 4,
+  "storage_name": "mmap_store",
+  "truncate_data": true,
+  "quantize": false,
+  "mmap": true
+}' 200 "56. Inicializar VFSManager con mmap" $SERVER_PID
+
+# 57. Insertar tres vectores y volcarlos al archivo de datos
+for i in 1 2 3; do
+  test_endpoint POST "/vectors" "{
+    \"values\": [$i.0, 0.0, 0.0, 1.0],
+    \"name\": \"Vector $i\",
+    \"tags\": [\"mmap\"]
+  }" 201 "57. Insertar vector $i de 3" $SERVER_PID
+done
+test_endpoint POST "/flush" '' 200 "57. Volcar la memtable" $SERVER_PID
+
+# 58. Leer y buscar a través de la proyección
+test_endpoint GET "/vectors/2" "" 200 "58. Obtener vector con mmap" $SERVER_PID
+check_response '.values == [2.0, 0.0, 0.0, 1.0]' "58. El vector leído con mmap conserva sus valores"
+test_endpoint POST "/search" '{
+  "values": [2.9, 0.0, 0.0, 1.0],
+  "top_k": 3,
+  "search_type": "exact",
+  "distance_method": "euclidean"
+}' 200 "58. Búsqueda exacta con mmap" $SERVER_PID
+check_response '[.results[].id] == [3, 2, 1]' "58. La búsqueda exacta con mmap ordena los tres vectores"
+
+# 59. Un vector volcado después de abrir la proyección también se lee
+test_endpoint POST "/vectors" '{
+  "values": [4.0, 0.0, 0.0, 1.0],
+  "name": "Vector 4",
+  "tags": ["mmap"]
+}' 201 "59. Insertar un cuarto vector" $SERVER_PID
+test_endpoint POST "/flush" '' 200 "59. Volcar la memtable" $SERVER_PID
+test_endpoint GET "/vectors/4" "" 200 "59. Obtener el vector añadido tras proyectar el archivo" $SERVER_PID
+check_response '.values == [4.0, 0.0, 0.0, 1.0]' "59. El vector añadido conserva sus valores"
+
+# 60. Tras compactar, la proyección apunta al archivo nuevo
+test_endpoint DELETE "/vectors/1" '' 200 "60. Borrar un vector" $SERVER_PID
+test_endpoint POST "/compact" '' 200 "60. Compactar con mmap" $SERVER_PID
+test_endpoint GET "/vectors/3" "" 200 "60. Obtener vector tras compactar" $SERVER_PID
+check_response '.values == [3.0, 0.0, 0.0, 1.0]' "60. El vector compactado conserva sus valores"
+
+# 61. Colección para comprobar que el grafo HNSW se carga al reiniciar
+reset_server
+test_endpoint POST "/collections" '{
+  "name": "restart_graph",
+  "vector_dimension": 8,
+  "hnsw": { "m": 4, "ef_construction": 20 }
+}' 201 "61. Crear colección para reiniciar el grafo" $SERVER_PID
+
+# 62. Insertar vectores, borrar algunos y guardar el snapshot con el grafo
+insert_vectors restart_graph 300 8
+for id in 10 20 30; do
+  test_endpoint DELETE "/collections/restart_graph/vectors/$id" '' 200 "62. Borrar el vector $id" $SERVER_PID
+done
+test_endpoint POST "/collections/restart_graph/snapshot" '' 200 "62. Guardar snapshot con el grafo" $SERVER_PID
+
+# 63. Resultados de referencia
+GRAPH_RESULTS=()
+for q in 1001 1002 1003; do
+  test_endpoint POST "/collections/restart_graph/search" "$(query_body $q 8 10)" 200 "63. Búsqueda aproximada $q antes de reiniciar" $SERVER_PID
+  GRAPH_RESULTS+=("$(search_results)")
+done
+
+# 64. Tras reiniciar, el grafo se carga de hnsw.bin y da los mismos resultados
+crash_server
+LOG_START=$(wc -l < server.log)
+start_server
+for n in 0 1 2; do
+  q=$((1001 + n))
+  test_endpoint POST "/collections/restart_graph/search" "$(query_body $q 8 10)" 200 "64. Búsqueda aproximada $q tras reiniciar" $SERVER_PID
+  check_response '[.results[] | [.id, .distance]] == $before' "64. La búsqueda $q da los mismos resultados que antes de reiniciar" --argjson before "${GRAPH_RESULTS[$n]}"
+done
+if tail -n +$((LOG_START + 1)) server.log | grep -q "reconstruyéndolo\|No se pudo cargar el índice HNSW\|No se pudo ponerse al día"; then
+  echo "❌ Error: El grafo HNSW se reconstruyó en lugar de cargarse"
+  kill -9 $SERVER_PID
+  exit 1
+fi
+echo "✅ Éxito: 64. El grafo HNSW se cargó sin reconstruirlo"
+
+# 65. Borrar la colección
+test_endpoint DELETE "/collections/restart_graph" '' 200 "65. Borrar colección del grafo" $SERVER_PID
+
+# 66. Dos colecciones con la misma semilla HNSW y los mismos vectores.
+# Con un grafo tan pequeño (m = 2) la búsqueda aproximada falla a menudo, así que otra semilla daría otros resultados.
+for collection in seed_a seed_b; do
+  test_endpoint POST "/collections" "{
+    \"name\": \"$collection\",
+    \"vector_dimension\": 16,
+    \"hnsw\": { \"m\": 2, \"ef_construction\": 8, \"seed\": 42 }
+  }" 201 "66. Crear colección $collection con semilla 42" $SERVER_PID
+  insert_vectors $collection 300 16
+done
+
+# 67. Con la misma semilla el grafo es el mismo, así que las búsquedas dan los mismos resultados
+for q in 1001 1002 1003 1004 1005; do
+  test_endpoint POST "/collections/seed_a/search" "$(query_body $q 16 10)" 200 "67. Búsqueda aproximada $q en seed_a" $SERVER_PID
+  SEED_RESULTS=$(search_results)
+  test_endpoint POST "/collections/seed_b/search" "$(query_body $q 16 10)" 200 "67. Búsqueda aproximada $q en seed_b" $SERVER_PID
+  check_response '[.results[] | [.id, .distance]] == $a' "67. La búsqueda $q da los mismos resultados con la misma semilla" --argjson a "$SEED_RESULTS"
+done
+
+# 68. Borrar las colecciones
+test_endpoint DELETE "/collections/seed_a" '' 200 "68. Borrar colección seed_a" $SERVER_PID
+test_endpoint DELETE "/collections/seed_b" '' 200 "68. Borrar colección seed_b" $SERVER_PID
+
+# 69. Colección para comprobar los borrados en el grafo HNSW
+test_endpoint POST "/collections" '{
+  "name": "hnsw_delete",
+  "vector_dimension": 8,
+  "hnsw": { "m": 4, "ef_construction": 20 }
+}' 201 "69. Crear colección para borrar nodos" $SERVER_PID
+insert_vectors hnsw_delete 200 8
+
+# 70. Borrar el 40% de los vectores: el grafo no se reconstruye al borrar
+delete_vectors hnsw_delete 1 80
+test_endpoint GET "/collections/hnsw_delete/stats" "" 200 "70. Estadísticas tras los borrados" $SERVER_PID
+check_response '.hnsw.live == 120 and .hnsw.deleted == 80' "70. Los nodos borrados siguen marcados en el grafo"
+
+# 71. La búsqueda devuelve top_k vectores vigentes
+for q in 1001 1002 1003; do
+  test_endpoint POST "/collections/hnsw_delete/search" "$(query_body $q 8 10)" 200 "71. Búsqueda aproximada $q tras borrar" $SERVER_PID
+  check_response '(.results | length == 10) and ([.results[].id] | unique | length == 10) and all(.results[]; .id > 80)' "71. La búsqueda $q devuelve 10 vectores vigentes distintos"
+done
+
+# 72. La primera búsqueda reconstruyó el grafo solo con los vectores vigentes
+test_endpoint GET "/collections/hnsw_delete/stats" "" 200 "72. Estadísticas tras buscar" $SERVER_PID
+check_response '.hnsw.nodes == 120 and .hnsw.deleted == 0' "72. El grafo se reconstruyó antes de buscar"
+
+# 73. Borrar la colección
+test_endpoint DELETE "/collections/hnsw_delete" '' 200 "73. Borrar colección con nodos borrados" $SERVER_PID
+
+# 74. Dos colecciones con la misma semilla que reconstruyen el grafo con 1 y 4 hilos
+for threads in 1 4; do
+  test_endpoint POST "/collections" "{
+    \"name\": \"threads_$threads\",
+    \"vector_dimension\": 16,
+    \"hnsw\": { \"m\": 2, \"ef_construction\": 8, \"seed\": 7, \"build_threads\": $threads }
+  }" 201 "74. Crear colección con build_threads = $threads" $SERVER_PID
+  insert_vectors threads_$threads 600 16
+  test_endpoint POST "/collections/threads_$threads/flush" '' 200 "74. Volcar la memtable de threads_$threads" $SERVER_PID
+done
+
+# 75. Sin hnsw.bin, al reiniciar el grafo se reconstruye desde vectors.dat con build_threads hilos
+crash_server
+rm -f "$RUN_DIR/state/threads_1/hnsw.bin" "$RUN_DIR/state/threads_4/hnsw.bin"
+start_server
+
+# 76. El grafo reconstruido no depende del número de hilos: 20 búsquedas aproximadas dan los mismos resultados
+echo -e "\n===> 76. Comparar 20 búsquedas aproximadas con los grafos de 1 y 4 hilos"
+ONE_THREAD_RESULTS=$(for q in $(seq 1001 1020); do search_quiet threads_1 "$(query_body $q 16 10)"; done)
+FOUR_THREAD_RESULTS=$(for q in $(seq 1001 1020); do search_quiet threads_4 "$(query_body $q 16 10)"; done)
+if [ "$ONE_THREAD_RESULTS" != "$FOUR_THREAD_RESULTS" ]; then
+  echo "❌ Error: Los grafos construidos con 1 y 4 hilos dan resultados distintos"
+  kill -9 $SERVER_PID
+  exit 1
+fi
+echo "✅ Éxito: 76. Las búsquedas dan los mismos resultados con 1 y 4 hilos"
+
+# 77. Borrar las colecciones
+test_endpoint DELETE "/collections/threads_1" '' 200 "77. Borrar colección threads_1" $SERVER_PID
+test_endpoint DELETE "/collections/threads_4" '' 200 "77. Borrar colección threads_4" $SERVER_PID
+
+# 78. Colección con un índice IVF de 4 listas
+test_endpoint POST "/collections" '{
+  "name": "ivf_disk",
+  "vector_dimension": 8,
+  "ivf": { "nlist": 4, "nprobe": 4 }
+}' 201 "78. Crear colección IVF" $SERVER_PID
+insert_vectors ivf_disk 100 8
+test_endpoint POST "/collections/ivf_disk/flush" '' 200 "78. Volcar la memtable" $SERVER_PID
+
+# 79. Recorriendo todas las listas, la búsqueda IVF da los mismos vectores que la exacta
+for q in 1001 1002 1003; do
+  test_endpoint POST "/collections/ivf_disk/search" "$(query_body $q 8 10 | jq -c '.search_type = "exact"')" 200 "79. Búsqueda exacta $q" $SERVER_PID
+  EXACT_IDS=$(jq -c '[.results[].id]' tmp_response.json)
+  test_endpoint POST "/collections/ivf_disk/search" "$(query_body $q 8 10 | jq -c '.search_type = "ivf"')" 200 "79. Búsqueda IVF $q" $SERVER_PID
+  check_response '[.results[].id] == $exact' "79. La búsqueda IVF $q coincide con la exacta" --argjson exact "$EXACT_IDS"
+done
+
+# 80. Las listas están en disco, una por centroide, y solo guardan los ids (8 bytes por vector)
+IVF_DIR="$RUN_DIR/data/ivf_disk/ivf"
+LIST_FILES=$(ls "$IVF_DIR"/list_*.ids 2>/dev/null | wc -l)
+LIST_BYTES=$(cat "$IVF_DIR"/list_*.ids 2>/dev/null | wc -c)
+if [ ! -f "$IVF_DIR/index.bin" ] || [ "$LIST_FILES" -ne 4 ] || [ "$LIST_BYTES" -ne 800 ]; then
+  echo "❌ Error: Se esperaban index.bin y 4 listas con 800 bytes en $IVF_DIR (hay $LIST_FILES listas con $LIST_BYTES bytes)"
+  kill -9 $SERVER_PID
+  exit 1
+fi
+echo "✅ Éxito: 80. El índice IVF guarda una lista de ids por centroide"
+
+# 81. Tras borrar un vector y reiniciar, el índice se carga sin volver a entrenarlo y no devuelve el borrado
+test_endpoint POST "/collections/ivf_disk/search" "$(query_body 1001 8 1 | jq -c '.search_type = "ivf"')" 200 "81. Vector más cercano a la consulta 1001" $SERVER_PID
+NEAREST_ID=$(jq '.results[0].id' tmp_response.json)
+test_endpoint DELETE "/collections/ivf_disk/vectors/$NEAREST_ID" '' 200 "81. Borrar el vector más cercano" $SERVER_PID
+crash_server
+LOG_START=$(wc -l < server.log)
+start_server
+test_endpoint POST "/collections/ivf_disk/search" "$(query_body 1001 8 10 | jq -c '.search_type = "exact"')" 200 "81. Búsqueda exacta tras reiniciar" $SERVER_PID
+EXACT_IDS=$(jq -c '[.results[].id]' tmp_response.json)
+test_endpoint POST "/collections/ivf_disk/search" "$(query_body 1001 8 10 | jq -c '.search_type = "ivf"')" 200 "81. Búsqueda IVF tras reiniciar" $SERVER_PID
+check_response '[.results[].id] == $exact and all(.results[]; .id != $deleted)' "81. La búsqueda IVF tras reiniciar coincide con la exacta y no devuelve el borrado" --argjson exact "$EXACT_IDS" --argjson deleted "$NEAREST_ID"
+if tail -n +$((LOG_START + 1)) server.log | grep -q "Entrenando el índice IVF"; then
+  echo "❌ Error: El índice IVF se volvió a entrenar en lugar de cargarse"
+  kill -9 $SERVER_PID
+  exit 1
+fi
+echo "✅ Éxito: 81. El índice IVF se cargó sin volver a entrenarlo"
+
+# 82. Borrar la colección
+test_endpoint DELETE "/collections/ivf_disk" '' 200 "82. Borrar colección IVF" $SERVER_PID
+
+# 83. Tipo de búsqueda desconocido
+test_endpoint POST "/collections" '{
+  "name": "search_types",
+  "vector_dimension": 4
+}' 201 "83. Crear colección search_types" $SERVER_PID
+test_endpoint POST "/collections/search_types/search" '{
+  "values": [1.0, 2.0, 3.0, 4.0],
+  "top_k": 3,
+  "search_type": "hnsw_typo"
+}' 400 "83. Rechazar un tipo de búsqueda desconocido" $SERVER_PID
+check_response '.error == "Invalid search type: hnsw_typo"' "83. El error indica el tipo de búsqueda"
+test_endpoint DELETE "/collections/search_types" '' 200 "83. Borrar colección search_types" $SERVER_PID
+
+# 84. Los nombres sin prefijo usan SIMD y "scalar_" pide la versión escalar: las dos dan las mismas distancias
+test_endpoint POST "/collections" '{
+  "name": "scalar_opt_out",
+  "vector_dimension": 20,
+  "distance_method": "cosine"
+}' 201 "84. Crear colección con distancia coseno" $SERVER_PID
+insert_vectors scalar_opt_out 30 20
+for method in cosine scalar_cosine euclidean scalar_euclidean; do
+  test_endpoint POST "/collections/scalar_opt_out/search" "$(query_body 1001 20 5 | jq -c --arg m "$method" '.search_type = "exact" | .distance_method = $m')" 200 "84. Búsqueda exacta con $method" $SERVER_PID
+  cp tmp_response.json "tmp_$method.json"
+done
+for pair in "cosine scalar_cosine" "euclidean scalar_euclidean"; do
+  set -- $pair
+  if ! jq -e --slurpfile s "tmp_$2.json" '[.results[].id] == [$s[0].results[].id]
+      and ([.results[].distance] | to_entries | all(. as $e | ($e.value - $s[0].results[$e.key].distance) | fabs < 0.0001))' "tmp_$1.json" > /dev/null; then
+    echo "❌ Error: \"$1\" y \"$2\" no devuelven los mismos resultados"
+    kill -9 $SERVER_PID
+    exit 1
+  fi
+  echo "✅ Éxito: 84. \"$1\" y \"$2\" devuelven los mismos resultados"
+done
+rm -f tmp_cosine.json tmp_scalar_cosine.json tmp_euclidean.json tmp_scalar_euclidean.json
+test_endpoint DELETE "/collections/scalar_opt_out" '' 200 "84. Borrar colección scalar_opt_out" $SERVER_PID
+
+# 85. Colección cuantizada: los índices calculan las distancias sobre vistas de los valores, sin copiarlos
+test_endpoint POST "/collections" '{
+  "name": "quantized_views",
+  "vector_dimension": 16,
+  "quantize": true
+}' 201 "85. Crear colección cuantizada" $SERVER_PID
+insert_vectors quantized_views 300 16
+test_endpoint POST "/collections/quantized_views/flush" '' 200 "85. Volcar la memtable" $SERVER_PID
+
+# 86. Cada tipo de búsqueda devuelve las mismas distancias que se obtienen con los valores descuantizados de la respuesta
+QUANTIZED_QUERY=$(query_body 1000 16 5 | jq -c .values)
+DISTANCE_FILTER='def distance($v):
+    if $m == "cosine" then 1 - ([$q, $v] | transpose | map(.[0] * .[1]) | add) / (($q | map(. * .) | add | sqrt) * ($v | map(. * .) | add | sqrt))
+    else [$q, $v] | transpose | map((.[0] - .[1]) * (.[0] - .[1])) | add | sqrt end;
+  .results | length == 5 and all(.[]; (.distance - distance(.vector.values)) | fabs < 0.0001)'
+for method in euclidean cosine; do
+  search_types="exact ivf ivf_pq vamana"
+  if [ "$method" == "cosine" ]; then
+    search_types="$search_types lsh"
+  fi
+  for search_type in $search_types; do
+    test_endpoint POST "/collections/quantized_views/search" "$(jq -nc --argjson q "$QUANTIZED_QUERY" --arg t "$search_type" --arg m "$method" '{values: $q, top_k: 5, search_type: $t, distance_method: $m, nprobe: 64, rerank: 100, ef_search: 50, probes: 16}')" 200 "86. Búsqueda $search_type ($method) en la colección cuantizada" $SERVER_PID
+    check_response "$DISTANCE_FILTER" "86. Las distancias de la búsqueda $search_type ($method) coinciden con las de los valores descuantizados" --argjson q "$QUANTIZED_QUERY" --arg m "$method"
+  done
+done
+
+# 87. Borrar la colección
+test_endpoint DELETE "/collections/quantized_views" '' 200 "87. Borrar colección cuantizada" $SERVER_PID
+
 # Limpieza
 rm -f tmp_response.json
 
@@ -11,7 +11,7 @@ use std::thread;
 use std::io;
 use colored::*;
 
-use vfs::tcp::{handle_request, ServerState};
+use vfs::tcp::{handle_request, recover_collections};
 use vfs::storage_manager::{VFSManager, ResetOptions};
 
 const DEFAULT_PORT: &str="9001";
@@ -27,10 +27,18 @@ pub fn print_welcome_message() {
     println!("{}", "║   ➤ POST   /init                                     ║".white());
     println!("{}", "║   ➤ POST   /vectors                                  ║".white());
     println!("{}", "║   ➤ GET    /vectors/<id>                             ║".white());
+    println!("{}", "║   ➤ PUT    /vectors/<id>                             ║".white());
+    println!("{}", "║   ➤ DELETE /vectors/<id>                             ║".white());
     println!("{}", "║   ➤ POST   /search                                   ║".white());
     println!("{}", "║   ➤ POST   /flush                                    ║".white());
     println!("{}", "║   ➤ POST   /snapshot                                 ║".white());
     println!("{}", "║   ➤ POST   /restore                                  ║".white());
+    println!("{}", "║   ➤ POST   /compact                                  ║".white());
+    println!("{}", "║   ➤ GET    /stats                                    ║".white());
+    println!("{}", "║   ➤ POST   /collections                              ║".white());
+    println!("{}", "║   ➤ GET    /collections                              ║".white());
+    println!("{}", "║   ➤ DELETE /collections/<name>                       ║".white());
+    println!("{}", "║   ➤ *      /collections/<name>/<ruta>                ║".white());
     println!("{}", "║                                                      ║".bright_blue());
     println!("{}", "║  Contacto: raul.moldes.work@gmail.com                ║".bright_green());
     println!("{}", "║                                                      ║".bright_blue());
@@ -46,7 +54,8 @@ fn main() -> io::Result<()> {
     let port = args.get(2).cloned().unwrap_or_else(|| DEFAULT_PORT.to_string());
     let address = format!("127.0.0.1:{}", port);
    // println!("Servidor escuchando en http://127.0.0.1:{}", port);
-    let state = Arc::new(Mutex::new(None::<ServerState>));
+    // Reproducir el WAL de cada colección antes de aceptar peticiones.
+    let state = Arc::new(Mutex::new(recover_collections()));
      // Escuchar en el puerto 7878
      let listener = TcpListener::bind(&address).expect("No se pudo abrir el puerto");
 
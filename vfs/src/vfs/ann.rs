@@ -122,10 +122,14 @@ use core::{
     iter::{Cloned, TakeWhile},
     slice::Iter,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
-use super::vector::VFSVector;
+use super::vector::{VFSVector, VectorView};
+use super::rank::MAX_EF_SEARCH;
 use rand_core::{RngCore, SeedableRng};
+use rand::Rng;
+use rand::rngs::SmallRng;
+use serde::{Serialize, Deserialize};
 
 // Enum que representa los dos tipos de capa (Zero y NonZero)
 pub enum Layer<T> {
@@ -175,15 +179,13 @@ pub trait HasNeighbors<'a, 'b> {
 
 // Nodo de capa zero
 #[derive(Clone, Debug)]
-pub struct NeighborNodes<const N: usize> {
-    // Vecinos de este nodo
-    pub neighbors: [usize; N],
+pub struct NeighborNodes {
+    // Vecinos de este nodo. Tiene siempre el máximo de vecinos de su capa (M o M0); los huecos valen `!0`.
+    pub neighbors: Vec<usize>,
 }
 
 //
-impl<'a, 'b: 'a, // b' vive al menos tanto como a'
-// N es el número de vecinos.
-const N: usize> HasNeighbors<'a, 'b> for NeighborNodes<N> {
+impl<'a, 'b: 'a> HasNeighbors<'a, 'b> for NeighborNodes { // b' vive al menos tanto como a'
     /// Define el tipo de iterador que se usa para iterar sobre los vecinos.
     // Cloned hace que se clonen los valores, de manera que se devuelve usize por valor, no por referencia.
     // Take While toma elementos siempre que una condición se cumple.
@@ -198,16 +200,16 @@ const N: usize> HasNeighbors<'a, 'b> for NeighborNodes<N> {
 
 
 /// Un nodo de cualquier otra capa.
-pub struct Node<const N: usize> {
+pub struct Node {
     /// El nodo de la capa cero al que apunta este nodo.
     pub zero_node: usize,
     /// El nodo en la capa siguiente al que este nodo apunta.
     pub next_node: usize,
     /// Los vecinos de este nodo.
-    pub neighbors: NeighborNodes<N>,
+    pub neighbors: NeighborNodes,
 }
 
-impl<'a, 'b: 'a, const N: usize> HasNeighbors<'a, 'b> for Node<N> {
+impl<'a, 'b: 'a> HasNeighbors<'a, 'b> for Node {
     type NeighborIter = TakeWhile<Cloned<Iter<'a, usize>>, fn(&usize) -> bool>;
 
     fn get_neighbors(&'b self) -> Self::NeighborIter {
@@ -219,46 +221,289 @@ impl<'a, 'b: 'a, const N: usize> HasNeighbors<'a, 'b> for Node<N> {
 
 
 
+pub const DEFAULT_M: usize = 16;
+pub const DEFAULT_M0: usize = 40;
+pub const DEFAULT_EF_CONSTRUCTION: usize = 400;
+const MAX_NEIGHBORS: usize = 1024;
+const MAX_EF_CONSTRUCTION: usize = 10_000;
+/// Fracción de nodos borrados a partir de la cual `VFSANNIndex` reconstruye el grafo con los nodos vigentes (en la siguiente búsqueda).
+pub const MAX_DELETED_FRACTION: f64 = 0.3;
+/// Tamaño máximo de los lotes de `insert_parallel`.
+const PARALLEL_BATCH_SIZE: usize = 256;
+pub const MAX_BUILD_THREADS: usize = 256;
+
+// Hilos por defecto para construir el grafo: los que ofrezca la máquina.
+pub fn default_build_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// Parámetros de construcción del grafo. Se eligen al crear la colección y no cambian después.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HnswParams {
+    pub m: usize,               // Máximo de vecinos por nodo en las capas superiores.
+    pub m0: usize,              // Máximo de vecinos por nodo en la capa cero.
+    pub ef_construction: usize, // Candidatos que se exploran al insertar un nodo.
+    pub level_multiplier: f64,  // m_L. Con 0 todos los nodos se quedan en la capa cero.
+    pub seed: Option<u64>,      // Semilla del generador de niveles. Sin semilla se usa la semilla por defecto del generador.
+    pub heuristic: bool,        // Elegir vecinos con la heurística de diversidad del paper en vez de solo por distancia.
+    pub keep_pruned: bool,      // Con la heurística, rellenar los huecos con los candidatos descartados.
+}
+
+// La regla práctica del paper: m_L = 1/ln(M).
+fn default_level_multiplier(m: usize) -> f64 {
+    libm::log(m as f64).recip()
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: DEFAULT_M,
+            m0: DEFAULT_M0,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+            level_multiplier: default_level_multiplier(DEFAULT_M),
+            seed: None,
+            heuristic: true,
+            keep_pruned: false,
+        }
+    }
+}
+
+impl HnswParams {
+    // Construye los parámetros a partir de los valores opcionales de una petición.
+    // Si solo se indica M, M0 vale el mayor entre 40 y 2·M, y m_L vale 1/ln(M).
+    pub fn new(
+        m: Option<usize>,
+        m0: Option<usize>,
+        ef_construction: Option<usize>,
+        level_multiplier: Option<f64>,
+        seed: Option<u64>,
+        heuristic: Option<bool>,
+        keep_pruned: Option<bool>,
+    ) -> Result<Self, String> {
+        let m = m.unwrap_or(DEFAULT_M);
+        let params = Self {
+            m,
+            m0: m0.unwrap_or(DEFAULT_M0.max(2 * m)),
+            ef_construction: ef_construction.unwrap_or(DEFAULT_EF_CONSTRUCTION),
+            level_multiplier: level_multiplier.unwrap_or_else(|| default_level_multiplier(m.max(2))),
+            seed,
+            heuristic: heuristic.unwrap_or(true),
+            keep_pruned: keep_pruned.unwrap_or(false),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.m < 2 || self.m > MAX_NEIGHBORS {
+            return Err(format!("m must be between 2 and {}", MAX_NEIGHBORS));
+        }
+        if self.m0 < self.m || self.m0 > MAX_NEIGHBORS {
+            return Err(format!("m0 must be between m ({}) and {}", self.m, MAX_NEIGHBORS));
+        }
+        if self.ef_construction == 0 || self.ef_construction > MAX_EF_CONSTRUCTION {
+            return Err(format!("ef_construction must be between 1 and {}", MAX_EF_CONSTRUCTION));
+        }
+        // El límite es el valor por defecto para el M más pequeño: con más, el grafo tendría demasiadas capas.
+        let max_level_multiplier = default_level_multiplier(2);
+        if !(0.0..=max_level_multiplier).contains(&self.level_multiplier) {
+            return Err(format!("level_multiplier must be between 0 and {:.3}", max_level_multiplier));
+        }
+        Ok(())
+    }
+}
+
+// Generador pseudoaleatorio de niveles. Con la misma semilla y el mismo orden de inserción se obtiene el mismo grafo.
+fn seeded_prng<R: SeedableRng>(seed: Option<u64>) -> R {
+    match seed {
+        Some(seed) => R::seed_from_u64(seed),
+        None => R::from_seed(R::Seed::default()),
+    }
+}
+
+// Copia del grafo que se puede guardar en disco (sin la función de distancia ni el generador pseudoaleatorio).
+// Los vecinos se guardan sin los huecos `!0`.
+#[derive(Serialize, Deserialize)]
+pub struct HnswSnapshot<T> {
+    zero: Vec<Vec<usize>>,
+    features: Vec<T>,
+    layers: Vec<Vec<(usize, usize, Vec<usize>)>>, // (zero_node, next_node, vecinos)
+    params: HnswParams,
+    level_draws: u64,
+    deleted: Vec<usize>,
+}
+
+// Pasa los vecinos guardados a una lista de `capacity` posiciones, rellenando con `!0`.
+fn neighbors_from_vec(saved: &[usize], capacity: usize, node_count: usize) -> Result<NeighborNodes, String> {
+    if saved.len() > capacity {
+        return Err(format!("A node has {} neighbors, the maximum is {}", saved.len(), capacity));
+    }
+    let mut neighbors = vec![!0; capacity];
+    for (d, &s) in neighbors.iter_mut().zip(saved.iter()) {
+        if s >= node_count {
+            return Err(format!("Neighbor {} is out of range", s));
+        }
+        *d = s;
+    }
+    Ok(NeighborNodes { neighbors })
+}
+
+// Posición de cada nodo en las capas no-zero donde aparece. Las capas que falten quedan a `!0`.
+fn layer_positions(layers: &[Vec<Node>]) -> HashMap<usize, Vec<usize>> {
+    let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (ix, layer) in layers.iter().enumerate() {
+        for (position, node) in layer.iter().enumerate() {
+            let node_positions = positions.entry(node.zero_node).or_default();
+            if node_positions.len() <= ix {
+                node_positions.resize(ix + 1, !0);
+            }
+            node_positions[ix] = position;
+        }
+    }
+    positions
+}
+
+
 // ---------------------------------------------------------------------------------------------------------------------------------------------------//
 
 // IMPLEMENTACIÓN DE HNSW //
-pub struct Hnsw<F, T, R, const M: usize, const M0: usize> 
+pub struct Hnsw<F, T, R> 
 where
     F: Fn(&T, &T) -> f32,
 {
     /// Función de distancia entre dos VFSVector:
     distance_fn: F, // debe ser una función que devuelva float32
     /// Contiene la capa zero.
-    zero: Vec<NeighborNodes<M0>>,
+    zero: Vec<NeighborNodes>,
     /// Features de la capa zero
     features: Vec<T>,
     /// Cada una de las capas no-zero
-    layers: Vec<Vec<Node<M>>>,
+    layers: Vec<Vec<Node>>,
     /// Generador pseudoaleatorio
     prng: R,
-    /// EfConstruction
-    ef_construction: usize,
+    /// M, M0, efConstruction, m_L y semilla
+    params: HnswParams,
+    /// Niveles generados hasta ahora. Al cargar un snapshot se avanza el generador lo mismo,
+    /// para que las inserciones siguientes reciban los mismos niveles que sin guardar y cargar.
+    level_draws: u64,
+    /// Nodos borrados (índices de la capa cero). Siguen en el grafo para no romper los caminos
+    /// que pasan por ellos, pero no se devuelven en las búsquedas ni reciben enlaces nuevos.
+    deleted: HashSet<usize>,
+    /// Posición de cada nodo en las capas no-zero donde aparece (la primera, la de la capa 1).
+    /// Sirve para encontrar un nodo borrado en sus capas sin recorrerlas.
+    upper_positions: HashMap<usize, Vec<usize>>,
 
 
 }
 
-impl<F, T, R, const M: usize, const M0: usize> Hnsw<F, T, R, M, M0>
+impl<F, T, R> Hnsw<F, T, R>
 where
     R: RngCore + SeedableRng,
     F: Fn(&T, &T) -> f32
 {
     /// Crea un nuevo índice HNSW
-    pub fn new(distance_fn: F, ef_construction: Option<usize>) -> Self {
+    pub fn new(distance_fn: F, params: HnswParams) -> Self {
         Self {
             distance_fn,
             zero: vec![],
             features: vec![],
             layers: vec![],
-            prng: R::from_seed(R::Seed::default()),
-            ef_construction: ef_construction.unwrap_or(400) // por defecto le pongo 400
+            prng: seeded_prng(params.seed),
+            params,
+            level_draws: 0,
+            deleted: HashSet::new(),
+            upper_positions: HashMap::new(),
         }
     }
 
+    pub fn params(&self) -> &HnswParams {
+        &self.params
+    }
+
+
+    /// Copia el grafo para guardarlo en disco.
+    pub fn snapshot(&self) -> HnswSnapshot<T>
+    where
+        T: Clone,
+    {
+        HnswSnapshot {
+            zero: self.zero.iter().map(|node| node.get_neighbors().collect()).collect(),
+            features: self.features.clone(),
+            layers: self.layers.iter()
+                .map(|layer| layer.iter()
+                    .map(|node| (node.zero_node, node.next_node, node.get_neighbors().collect()))
+                    .collect())
+                .collect(),
+            params: self.params,
+            level_draws: self.level_draws,
+            deleted: {
+                let mut deleted: Vec<usize> = self.deleted.iter().copied().collect();
+                deleted.sort_unstable();
+                deleted
+            },
+        }
+    }
+
+    /// Reconstruye un índice a partir de una copia hecha con `snapshot`.
+    /// Se comprueba que los índices de los vecinos sean válidos para no entrar en pánico al buscar.
+    pub fn from_snapshot(distance_fn: F, snapshot: HnswSnapshot<T>) -> Result<Self, String> {
+        let HnswSnapshot { zero, features, layers, params, level_draws, deleted } = snapshot;
+        if deleted.iter().any(|&node| node >= features.len()) {
+            return Err("A deleted node does not exist".to_string());
+        }
+        params.validate()?;
+        if zero.len() != features.len() {
+            return Err(format!("The graph has {} nodes but {} features", zero.len(), features.len()));
+        }
+
+        let zero = zero.iter()
+            .map(|saved| neighbors_from_vec(saved, params.m0, features.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut restored_layers: Vec<Vec<Node>> = Vec::with_capacity(layers.len());
+        for (ix, layer) in layers.iter().enumerate() {
+            // `next_node` apunta a la capa inferior: la capa cero para la primera capa no-cero.
+            let lower_len = if ix == 0 { features.len() } else { layers[ix - 1].len() };
+            let mut nodes = Vec::with_capacity(layer.len());
+            for (zero_node, next_node, saved) in layer {
+                if *zero_node >= features.len() || *next_node >= lower_len {
+                    return Err(format!("Layer {} points to a node that does not exist", ix + 1));
+                }
+                nodes.push(Node {
+                    zero_node: *zero_node,
+                    next_node: *next_node,
+                    neighbors: neighbors_from_vec(saved, params.m, layer.len())?,
+                });
+            }
+            if nodes.is_empty() {
+                return Err(format!("Layer {} is empty", ix + 1));
+            }
+            restored_layers.push(nodes);
+        }
+
+        let upper_positions = layer_positions(&restored_layers);
+        if upper_positions.values().any(|positions| positions.contains(&!0)) {
+            return Err("A node is missing from a lower layer".to_string());
+        }
+
+        let mut prng: R = seeded_prng(params.seed);
+        for _ in 0..level_draws {
+            prng.next_u64();
+        }
+
+        Ok(Self {
+            distance_fn,
+            zero,
+            features,
+            layers: restored_layers,
+            prng,
+            params,
+            level_draws,
+            deleted: deleted.into_iter().collect(),
+            upper_positions,
+        })
+    }
+
 
     fn initialize_searcher(&self, q: &T,  searcher: &mut Searcher) {
         // Clear the searcher.
@@ -305,7 +550,7 @@ where
             self.search_single_layer(q, searcher, Layer::NonZero(layer), cap);
             if ix + 1 == level {
                 let found = core::cmp::min(dest.len(), searcher.nearest.len());
-                dest.copy_from_slice(&searcher.nearest[..found]);
+                dest[..found].copy_from_slice(&searcher.nearest[..found]);
                 return &mut dest[..found];
             }
             self.lower_search(layer, searcher);
@@ -317,7 +562,7 @@ where
         self.search_zero_layer(q, searcher, cap);
 
         let found = core::cmp::min(dest.len(), searcher.nearest.len());
-        dest.copy_from_slice(&searcher.nearest[..found]);
+        dest[..found].copy_from_slice(&searcher.nearest[..found]);
         &mut dest[..found]
     }
 
@@ -347,7 +592,7 @@ where
         &self,
         q: &T,
         searcher: &mut Searcher,
-        layer: Layer<&[Node<M>]>,
+        layer: Layer<&[Node]>,
         cap: usize,
     ) {
 
@@ -374,6 +619,17 @@ where
                     // Si no lo hemos visto, calcular la distancia del nodo a q
                     let distance = (self.distance_fn)(q, &self.features[node_to_visit as usize]);
 
+                    // Los nodos borrados de la capa cero no entran en la lista de cercanos, pero se siguen
+                    // explorando si podrían llevar a un nodo mejor, para no perder las zonas que conectaban.
+                    if matches!(layer, Layer::Zero) && self.deleted.contains(&node_to_visit) {
+                        let promising = searcher.nearest.len() < cap
+                            || searcher.nearest.last().is_none_or(|worst| distance < worst.distance);
+                        if promising {
+                            searcher.candidates.push(Neighbor { index: neighbor, distance });
+                        }
+                        continue;
+                    }
+
                     // Intenta insertar en la cola de cercanos.
                     // Buscar la posicion del primer elemento donde la distancia deja de ser menor o igual a la que acabamos de calcular.
                     let pos = searcher.nearest.partition_point(|n| n.distance <= distance);
@@ -403,13 +659,15 @@ where
 
      /// Buscar en la capa zero, simplemente ejecutar search_single_layer con los parametros adecuados.
      fn search_zero_layer(&self, q: &T, searcher: &mut Searcher, cap: usize) {
+        // El punto de partida puede ser un nodo borrado: se mantiene como candidato, pero no como resultado.
+        searcher.nearest.retain(|n| !self.deleted.contains(&n.index));
         self.search_single_layer(q, searcher, Layer::Zero, cap);
     }
 
     /// Inicia la búsqueda para el siguiente nivel.
     ///
     /// `m` es el número máximo de vecinos a considerar durante la búsqueda.
-    fn lower_search(&self, layer: &[Node<M>], searcher: &mut Searcher) {
+    fn lower_search(&self, layer: &[Node], searcher: &mut Searcher) {
         // Limpiamos la lista de candidatos para actualizarla.
         searcher.candidates.clear();
         // Solo mantenemos  el primer candidato, como indica el paper original.
@@ -417,6 +675,10 @@ where
         // (Ver algoritmo 5 linea 5)
         let &Neighbor { index, distance } = searcher.nearest.first().unwrap();
         searcher.nearest.clear();
+        // Cada capa tiene su propia lista de visitados: un nodo visto en una capa superior
+        // tiene que poder evaluarse otra vez en la inferior, donde tiene otros vecinos.
+        searcher.seen.clear();
+        searcher.seen.insert(layer[index].zero_node);
         // Bajamos al nodo de la siguiente capa.
         let new_index = layer[index].next_node as usize;
         let candidate = Neighbor {
@@ -432,18 +694,12 @@ where
     fn insert(&mut self, q: T, searcher: &mut Searcher) -> usize {
         // Obtener el nivel de la característica.
         let level = self.random_level();
-        // Si el nivel no es el más alto, cap = ef_construction
-        let mut cap = if level >= self.layers.len() {
-            self.ef_construction
-        } else {
-            1
-        };
 
         // Si el índice esta vacío lo añadimos manualmente.
         if self.is_empty() {
             // Add the zero node unconditionally.
             self.zero.push(NeighborNodes {
-                neighbors: [!0; M0],
+                neighbors: vec![!0; self.params.m0],
                 // M0 es el número de nodos de la capa 0.
             });
             self.features.push(q);
@@ -454,44 +710,73 @@ where
                 let node = Node {
                     zero_node: 0,
                     next_node: 0,
-                    neighbors: NeighborNodes { neighbors: [!0; M] }, // M es el número de nodos de las capas no cero
+                    neighbors: NeighborNodes { neighbors: vec![!0; self.params.m] }, // M es el número de nodos de las capas no cero
                 };
-                self.layers.push(vec![node]);
+                self.push_layer_node(self.layers.len() + 1, node);
             }
             return 0;
         }
 
+        let candidates = self.insert_candidates(&q, level, searcher);
+        self.link(q, level, &candidates)
+    }
+
+    /// Busca los vecinos de un nodo nuevo de nivel `level` sin modificar el grafo.
+    /// Devuelve los candidatos de cada capa donde ya se puede enlazar (posición 0 = capa cero),
+    /// ordenados de menor a mayor distancia.
+    fn insert_candidates(&self, q: &T, level: usize, searcher: &mut Searcher) -> Vec<Vec<Neighbor>> {
+        let mut candidates = vec![Vec::new(); core::cmp::min(level, self.layers.len()) + 1];
+        // Si el nivel no es el más alto, cap = ef_construction
+        let mut cap = if level >= self.layers.len() {
+            self.params.ef_construction
+        } else {
+            1
+        };
+
         // Recargar searcher
-        self.initialize_searcher(&q, searcher);
+        self.initialize_searcher(q, searcher);
 
         // Realizamos una búsqueda aproximada hasta alzanzar el nivel deseado.
         for ix in (level..self.layers.len()).rev() {
             // Realizar búsqueda aproximada
-            self.search_single_layer(&q, searcher, Layer::NonZero(&self.layers[ix]), cap);
+            self.search_single_layer(q, searcher, Layer::NonZero(&self.layers[ix]), cap);
             // Bajamos la búsqueda.
             self.lower_search(&self.layers[ix], searcher);
             cap = if ix == level {
                 // Cuando alcanzamos el nivel deseado, actualizar cap para matchear ef_construction.
-                self.ef_construction
+                self.params.ef_construction
             } else {
                 1
             };
         }
 
-        // Nivel alcanzado, conectamos el nodo a sus vecinos, empleanso
+        // Nivel alcanzado, guardamos los vecinos de cada capa.
         for ix in (0..core::cmp::min(level, self.layers.len())).rev() {
             // Buscamos los vecinos de esta capa
-            self.search_single_layer(&q, searcher, Layer::NonZero(&self.layers[ix]), cap);
-            // Usar los resultados de la búsqueda para crear el nodo.
-            self.create_node(&q, &searcher.nearest, ix + 1);
+            self.search_single_layer(q, searcher, Layer::NonZero(&self.layers[ix]), cap);
+            candidates[ix + 1] = searcher.nearest.clone();
             // Bajar una capa.
             self.lower_search(&self.layers[ix], searcher);
-            cap = self.ef_construction;
+            cap = self.params.ef_construction;
+        }
+
+        // Vecinos en la capa cero
+        self.search_zero_layer(q, searcher, cap);
+        candidates[0] = searcher.nearest.clone();
+        candidates
+    }
+
+    /// Añade `q` al grafo con nivel `level`, enlazándolo con los candidatos de `insert_candidates`.
+    /// Las capas sin candidatos (por ejemplo, creadas después de la búsqueda) se enlazan sin vecinos.
+    fn link(&mut self, q: T, level: usize, candidates: &[Vec<Neighbor>]) -> usize {
+        // Conectamos el nodo a sus vecinos, de la capa más alta a la más baja.
+        for ix in (0..core::cmp::min(level, self.layers.len())).rev() {
+            let nearest = candidates.get(ix + 1).map_or(&[][..], |c| &c[..]);
+            self.create_node(&q, nearest, ix + 1);
         }
 
         // Conectar el nodo en la capa cero
-        self.search_zero_layer(&q, searcher, cap);
-        self.create_node(&q, &searcher.nearest, 0);
+        self.create_node(&q, &candidates[0], 0);
         // Añadir la feature a la capa cero.
         self.features.push(q);
 
@@ -501,17 +786,96 @@ where
             let node = Node {
                 zero_node,
                 next_node: self.layers.last().map(|l| l.len() - 1).unwrap_or(zero_node),
-                neighbors: NeighborNodes { neighbors: [!0; M] },
+                neighbors: NeighborNodes { neighbors: vec![!0; self.params.m] },
             };
-            self.layers.push(vec![node]);
+            self.push_layer_node(self.layers.len() + 1, node);
         }
         zero_node
     }
 
+    /// Inserta `items` en orden usando `threads` hilos. Devuelve el índice en la capa cero de cada uno.
+    ///
+    /// Se inserta por lotes: los niveles se sortean en orden (igual que en `insert`), la búsqueda de vecinos de
+    /// cada lote se reparte entre los hilos sobre el grafo tal como estaba al empezar el lote, y después los nodos
+    /// se enlazan uno a uno. Al enlazar, a los candidatos de la búsqueda se suman los nodos del mismo lote ya
+    /// enlazados, que la búsqueda no podía ver. Los lotes nunca son más grandes que el grafo, así que al principio
+    /// la construcción es casi secuencial. Solo el primer nodo de un grafo vacío se inserta con `insert`.
+    ///
+    /// El grafo no depende de `threads` (con un hilo se recorre el mismo camino), pero no es el mismo que daría
+    /// insertar los vectores uno a uno con `insert`: una semilla solo reproduce el grafo construido de la misma forma.
+    pub fn insert_parallel(&mut self, items: Vec<T>, threads: usize) -> Vec<usize>
+    where
+        F: Sync,
+        T: Sync,
+        R: Sync,
+    {
+        let mut searcher = Searcher::new(Vec::new());
+        let mut inserted = Vec::with_capacity(items.len());
+        let mut items = items.into_iter().peekable();
+
+        while items.peek().is_some() {
+            let batch_len = self.len().clamp(1, PARALLEL_BATCH_SIZE);
+            let batch: Vec<T> = items.by_ref().take(batch_len).collect();
+            // Un grafo vacío no tiene punto de entrada para buscar: el primer nodo se inserta directamente.
+            if self.is_empty() {
+                for q in batch {
+                    inserted.push(self.insert(q, &mut searcher));
+                }
+                continue;
+            }
+
+            let levels: Vec<usize> = batch.iter().map(|_| self.random_level()).collect();
+
+            // Fase paralela: búsqueda de vecinos sobre el grafo actual, que no se modifica.
+            let chunk_len = batch.len().div_ceil(threads.max(1));
+            let graph = &*self;
+            let mut found: Vec<Vec<Vec<Neighbor>>> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch.chunks(chunk_len)
+                    .zip(levels.chunks(chunk_len))
+                    .map(|(qs, ls)| scope.spawn(move || {
+                        let mut searcher = Searcher::new(Vec::new());
+                        qs.iter()
+                            .zip(ls)
+                            .map(|(q, &level)| graph.insert_candidates(q, level, &mut searcher))
+                            .collect::<Vec<_>>()
+                    }))
+                    .collect();
+                handles.into_iter()
+                    .flat_map(|handle| handle.join().expect("HNSW build thread panicked"))
+                    .collect()
+            });
+
+            // Fase secuencial: enlazar en orden. `placed` guarda el índice de cada nodo del lote en cada una de sus capas.
+            let mut placed: Vec<Vec<usize>> = Vec::with_capacity(batch.len());
+            for ((q, level), candidates) in batch.into_iter().zip(levels).zip(found.iter_mut()) {
+                candidates.resize(level + 1, Vec::new());
+                for (layer, nearest) in candidates.iter_mut().enumerate() {
+                    for indexes in &placed {
+                        if let Some(&index) = indexes.get(layer) {
+                            let distance = (self.distance_fn)(&q, self.layer_feature(layer, index));
+                            nearest.push(Neighbor { index, distance });
+                        }
+                    }
+                    nearest.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Greater));
+                    nearest.truncate(self.params.ef_construction);
+                }
+
+                let zero_node = self.link(q, level, candidates);
+                let indexes = (0..=level)
+                    .map(|layer| if layer == 0 { zero_node } else { self.layers[layer - 1].len() - 1 })
+                    .collect();
+                placed.push(indexes);
+                inserted.push(zero_node);
+            }
+        }
+        inserted
+    }
+
      /// Genera un nivel aleatorio
      fn random_level(&mut self) -> usize {
+        self.level_draws += 1;
         let uniform: f64 = self.prng.next_u64() as f64 / core::u64::MAX as f64;
-        (-libm::log(uniform) * libm::log(M as f64).recip()) as usize
+        (-libm::log(uniform) * self.params.level_multiplier) as usize
     }
 
 
@@ -530,7 +894,7 @@ where
         if level == 0 {
             item
         } else {
-            self.layers[level][item as usize].zero_node
+            self.layers[level - 1][item as usize].zero_node
         }
     }
 
@@ -564,6 +928,119 @@ where
         self.layer_len(level) == 0
     }
 
+    // Número de nodos marcados como borrados.
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.len()
+    }
+
+    pub fn is_deleted(&self, item: usize) -> bool {
+        self.deleted.contains(&item)
+    }
+
+    /// Marca como borrado el nodo `item` de la capa cero y repara los enlaces de sus vecinos en cada capa donde aparece.
+    /// Devuelve false si el nodo no existe o ya estaba borrado.
+    pub fn mark_deleted(&mut self, item: usize) -> bool {
+        if item >= self.len() || !self.deleted.insert(item) {
+            return false;
+        }
+        self.repair_neighbors(0, item);
+        let positions = self.upper_positions.get(&item).cloned().unwrap_or_default();
+        for (ix, node) in positions.into_iter().enumerate() {
+            self.repair_neighbors(ix + 1, node);
+        }
+        true
+    }
+
+    /// Añade `node` al final de la capa `level` (>= 1), creándola si es la siguiente a la última, y guarda su posición.
+    /// Los nodos se enlazan de la capa más alta a la más baja, así que las posiciones no llegan en orden.
+    fn push_layer_node(&mut self, level: usize, node: Node) {
+        if level > self.layers.len() {
+            self.layers.push(Vec::new());
+        }
+        let position = self.layers[level - 1].len();
+        let positions = self.upper_positions.entry(node.zero_node).or_default();
+        if positions.len() < level {
+            positions.resize(level, !0);
+        }
+        positions[level - 1] = position;
+        self.layers[level - 1].push(node);
+    }
+
+    /// Vuelve a construir el grafo solo con los nodos vigentes, en el orden en que se insertaron.
+    /// Devuelve el nuevo índice de cada nodo conservado. El generador aleatorio no se reinicia.
+    pub fn rebuild(&mut self) -> HashMap<usize, usize> {
+        let features = core::mem::take(&mut self.features);
+        let deleted = core::mem::take(&mut self.deleted);
+        self.zero.clear();
+        self.layers.clear();
+        self.upper_positions.clear();
+
+        let mut searcher = Searcher::new(Vec::new());
+        let mut remap = HashMap::with_capacity(features.len() - deleted.len());
+        for (old, feature) in features.into_iter().enumerate() {
+            if !deleted.contains(&old) {
+                let new = self.insert(feature, &mut searcher);
+                remap.insert(old, new);
+            }
+        }
+        remap
+    }
+
+    // Lista de vecinos de un nodo de cualquier capa.
+    fn neighbor_list(&self, level: usize, item: usize) -> &[usize] {
+        if level == 0 {
+            &self.zero[item].neighbors
+        } else {
+            &self.layers[level - 1][item].neighbors.neighbors
+        }
+    }
+
+    fn neighbors_mut(&mut self, level: usize, item: usize) -> &mut Vec<usize> {
+        if level == 0 {
+            &mut self.zero[item].neighbors
+        } else {
+            &mut self.layers[level - 1][item].neighbors.neighbors
+        }
+    }
+
+    /// Quita los enlaces hacia el nodo borrado `item` desde sus vecinos en la capa `level`.
+    /// Cada hueco se rellena con el vecino vigente de `item` más cercano que no estuviera ya enlazado,
+    /// para que la zona no quede desconectada.
+    fn repair_neighbors(&mut self, level: usize, item: usize) {
+        let affected: Vec<usize> = self.neighbor_list(level, item).iter()
+            .copied()
+            .take_while(|&n| n != !0)
+            .collect();
+        let replacements: Vec<usize> = affected.iter()
+            .copied()
+            .filter(|&n| !self.deleted.contains(&self.layer_item_id(level, n)))
+            .collect();
+
+        for &node in &affected {
+            let links = self.neighbors_mut(level, node);
+            let Some(pos) = links.iter().position(|&n| n == item) else {
+                continue;
+            };
+            // Quitamos el enlace manteniendo los huecos (!0) al final de la lista.
+            links.remove(pos);
+            links.push(!0);
+
+            let links = self.neighbor_list(level, node);
+            let feature = self.layer_feature(level, node);
+            let best = replacements.iter()
+                .copied()
+                .filter(|&candidate| candidate != node && !links.contains(&candidate))
+                .map(|candidate| (candidate, (self.distance_fn)(feature, self.layer_feature(level, candidate))))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Greater));
+
+            if let Some((candidate, _)) = best {
+                let links = self.neighbors_mut(level, node);
+                let empty_point = links.partition_point(|&n| n != !0);
+                links[empty_point] = candidate;
+            }
+        }
+    }
+
   
 
     /// Obtiene la feature de entrada.
@@ -579,12 +1056,23 @@ where
 
     /// Crea un nuevo nodo en un capa dada a partir de sus vecinos.
     fn create_node(&mut self, q: &T, nearest: &[Neighbor], layer: usize) {
+        // Los nodos nuevos no se enlazan con nodos borrados.
+        let nearest: Vec<Neighbor> = nearest.iter()
+            .filter(|n| !self.deleted.contains(&self.layer_item_id(layer, n.index)))
+            .copied()
+            .collect();
+        let capacity = if layer == 0 { self.params.m0 } else { self.params.m };
+        let candidates: Vec<(Neighbor, &T)> = nearest.iter()
+            .map(|&n| (n, self.layer_feature(layer, n.index)))
+            .collect();
+        let selected = self.select_neighbors(&candidates, capacity);
+
         if layer == 0 {
             let new_index = self.zero.len();
             // Obtener el siguiente índice de la capa cero.
-            let mut neighbors: [usize; M0] = [!0; M0];
-            for (d, s) in neighbors.iter_mut().zip(nearest.iter()) {
-                *d = s.index as usize; // Copiar nearest en neighbors
+            let mut neighbors = vec![!0; self.params.m0];
+            for (d, &s) in neighbors.iter_mut().zip(selected.iter()) {
+                *d = s; // Copiar los vecinos elegidos en neighbors
             }
             // Creamos el struct vecinos
             let node = NeighborNodes { neighbors };
@@ -600,9 +1088,9 @@ where
 
             // Equivalente en el resto de capas.
             let new_index = self.layers[layer - 1].len();
-            let mut neighbors: [usize; M] = [!0; M];
-            for (d, s) in neighbors.iter_mut().zip(nearest.iter()) {
-                *d = s.index;
+            let mut neighbors = vec![!0; self.params.m];
+            for (d, &s) in neighbors.iter_mut().zip(selected.iter()) {
+                *d = s;
             }
             let node = Node {
                 zero_node: self.zero.len(),
@@ -616,8 +1104,42 @@ where
             for neighbor in node.get_neighbors() {
                 self.add_neighbor(q, new_index, neighbor, layer);
             }
-            self.layers[layer - 1].push(node);
+            self.push_layer_node(layer, node);
+        }
+    }
+
+    /// Elige hasta `max` vecinos entre `candidates`, que vienen ordenados de menor a mayor distancia al nodo base.
+    ///
+    /// Sin la heurística se quedan los `max` más cercanos. Con ella (algoritmo 4 del paper) un candidato solo se
+    /// acepta si está más cerca del nodo base que de cualquier vecino ya elegido: así los vecinos apuntan en
+    /// direcciones distintas y los grupos de vectores muy parecidos no acaparan todos los enlaces.
+    /// Con `keep_pruned`, los huecos que queden se rellenan con los candidatos descartados, en orden de distancia.
+    fn select_neighbors(&self, candidates: &[(Neighbor, &T)], max: usize) -> Vec<usize> {
+        if !self.params.heuristic {
+            return candidates.iter().take(max).map(|(n, _)| n.index).collect();
+        }
+
+        let mut selected: Vec<(usize, &T)> = Vec::with_capacity(max);
+        let mut pruned = Vec::new();
+        for &(candidate, feature) in candidates {
+            if selected.len() == max {
+                break;
+            }
+            let diverse = selected.iter()
+                .all(|&(_, chosen)| candidate.distance < (self.distance_fn)(feature, chosen));
+            if diverse {
+                selected.push((candidate.index, feature));
+            } else {
+                pruned.push(candidate.index);
+            }
+        }
+
+        let mut selected: Vec<usize> = selected.into_iter().map(|(index, _)| index).collect();
+        if self.params.keep_pruned {
+            let missing = max - selected.len();
+            selected.extend(pruned.into_iter().take(missing));
         }
+        selected
     }
 
     /// 
@@ -651,40 +1173,23 @@ where
                     .neighbors[empty_point] = node_ix;
             }
         } else {
-            // En cualquier otro caso, hay que encontrar el peor vecino
-            let (worst_ix, worst_distance) = target_neighbors
-                .iter()
-                .enumerate()
-                .filter_map(|(ix, &n)| {
-                    // Computar la distancia de manera que sea lo mas alta posible si el vecino no se ha rellenado aun
-                    if n == !0 {
-                        None // usize::MAX  
-                    } else {
-                        // Calcular la peor distancia
-                        let distance = (self.distance_fn)(
-                            target_feature,
-                            &self.features[if layer == 0 {
-                                n
-                            } else {
-                                self.layers[layer - 1][n].zero_node
-                            }],
-                        );
-                        Some((ix, distance))
-                    }
+            // La lista está llena: se vuelven a elegir los vecinos entre los actuales y el nodo nuevo,
+            // que todavía no está en el grafo y por eso se compara con `q` directamente.
+            let mut candidates: Vec<(Neighbor, &T)> = target_neighbors.iter()
+                .map(|&n| {
+                    let feature = self.layer_feature(layer, n);
+                    (Neighbor { index: n, distance: (self.distance_fn)(target_feature, feature) }, feature)
                 })
-                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Greater))
-                .unwrap();
+                .collect();
+            candidates.push((Neighbor { index: node_ix, distance: (self.distance_fn)(q, target_feature) }, q));
+            candidates.sort_by(|a, b| a.0.distance.partial_cmp(&b.0.distance).unwrap_or(std::cmp::Ordering::Greater));
 
-            // Si la distancia mejora la peor distancia, añadimos el nodo en el último lugar
-            if (self.distance_fn)(q, target_feature) < worst_distance {
-                if layer == 0 {
-                    self.zero[target_ix as usize].neighbors[worst_ix] = node_ix;
-                } else {
-                    self.layers[layer - 1][target_ix as usize]
-                        .neighbors
-                        .neighbors[worst_ix] = node_ix;
-                }
-            }
+            let capacity = target_neighbors.len();
+            let selected = self.select_neighbors(&candidates, capacity);
+
+            let neighbors = self.neighbors_mut(layer, target_ix);
+            neighbors.fill(!0);
+            neighbors[..selected.len()].copy_from_slice(&selected);
         }
     }
 }
@@ -693,7 +1198,22 @@ where
 // Wrapper para poder integrarlo en el proyecto.
 
 
-pub type DefaultHNSW<F,R> = Hnsw<F, VFSVector, R, 16, 40>;
+pub type DefaultHNSW<F,R> = Hnsw<F, VFSVector, R>;
+
+// Copia de un `VFSANNIndex` para guardarlo en disco.
+#[derive(Serialize, Deserialize)]
+pub struct IndexSnapshot {
+    graph: HnswSnapshot<VFSVector>,
+    latest: HashMap<u64, usize>,
+}
+
+// Tamaño del grafo, para el endpoint de estadísticas. `nodes` incluye los nodos borrados que aún no se han eliminado al reconstruir.
+#[derive(Debug, Serialize)]
+pub struct IndexStats {
+    pub nodes: usize,
+    pub live: usize,
+    pub deleted: usize,
+}
 
 // IMPLEMENTACIÓN DE VFSANN//
 pub struct VFSANNIndex<F, R> 
@@ -703,6 +1223,9 @@ where
     /// Función de distancia entre dos VFSVector:
     hnsw: DefaultHNSW<F, R>,
     searcher: Searcher, 
+    // Nodo con la versión vigente de cada id. Los nodos de versiones antiguas o de vectores borrados
+    // se marcan como borrados en el grafo hasta que se reconstruye.
+    latest: HashMap<u64, usize>,
 
 
 }
@@ -714,19 +1237,80 @@ where
     R: RngCore + SeedableRng,
 {
 
-    pub fn new(distance_fn: F, ef_construction: Option<usize>) -> Self {
+    pub fn new(distance_fn: F, params: HnswParams) -> Self {
 
         let candidates = Vec::new();
         let searcher = Searcher::new(candidates);
-        let hnsw = DefaultHNSW::new(distance_fn, ef_construction);
+        let hnsw = DefaultHNSW::new(distance_fn, params);
 
         Self {
             hnsw,
-            searcher
+            searcher,
+            latest: HashMap::new(),
 
         }
     }
 
+    pub fn snapshot(&self) -> IndexSnapshot {
+        IndexSnapshot {
+            graph: self.hnsw.snapshot(),
+            latest: self.latest.clone(),
+        }
+    }
+
+    pub fn from_snapshot(distance_fn: F, snapshot: IndexSnapshot) -> Result<Self, String> {
+        let hnsw = DefaultHNSW::from_snapshot(distance_fn, snapshot.graph)?;
+        if snapshot.latest.values().any(|&node| node >= hnsw.len()) {
+            return Err("The id map points to a node that does not exist".to_string());
+        }
+
+        Ok(Self {
+            hnsw,
+            searcher: Searcher::new(Vec::new()),
+            latest: snapshot.latest,
+        })
+    }
+
+    pub fn params(&self) -> &HnswParams {
+        self.hnsw.params()
+    }
+
+    // Número de vectores vigentes en el índice.
+    pub fn len(&self) -> usize {
+        self.latest.len()
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            nodes: self.hnsw.len(),
+            live: self.len(),
+            deleted: self.hnsw.deleted_count(),
+        }
+    }
+
+    // Borra el vector `id` del grafo.
+    pub fn remove(&mut self, id: u64) {
+        if let Some(index) = self.latest.remove(&id) {
+            self.hnsw.mark_deleted(index);
+        }
+    }
+
+    // Si los nodos borrados superan `MAX_DELETED_FRACTION`, reconstruye el grafo y actualiza el mapa de ids.
+    // Se llama antes de cada búsqueda, no al borrar, para que los borrados y las actualizaciones no esperen a la reconstrucción.
+    fn maybe_rebuild(&mut self) {
+        if (self.hnsw.deleted_count() as f64) <= MAX_DELETED_FRACTION * self.hnsw.len() as f64 {
+            return;
+        }
+        let remap = self.hnsw.rebuild();
+        for node in self.latest.values_mut() {
+            *node = remap[node];
+        }
+    }
+
+    fn is_live(&self, index: usize) -> bool {
+        !self.hnsw.is_deleted(index)
+    }
+
     fn get_feature(&self, index: usize) -> Box<VFSVector> {
         // Wrapper para obtener el vector de características desde self.hnsw.features
         let feature_data = &self.hnsw.features[index];  // Ajusta esto según tu estructura
@@ -740,7 +1324,14 @@ where
     pub fn insert_one(&mut self, vfs_vector: VFSVector) -> usize {
       
         let mut searcher = self.get_searcher();
-        self.hnsw.insert(vfs_vector, &mut searcher)
+        let id = vfs_vector.id();
+        // La versión anterior del vector se borra antes de insertar la nueva, para que esta no se enlace con ella.
+        if let Some(old) = self.latest.remove(&id) {
+            self.hnsw.mark_deleted(old);
+        }
+        let index = self.hnsw.insert(vfs_vector, &mut searcher);
+        self.latest.insert(id, index);
+        index
 
     }
 
@@ -751,44 +1342,309 @@ where
 
     }
 
-    pub fn query(&mut self, vfs_vector: &VFSVector) -> io::Result<Vec<(Box<VFSVector>, f32)>> {
-        // Inicializar output como un vector mutable
+    /// Como `insert_many`, pero construyendo el grafo con `threads` hilos (ver `Hnsw::insert_parallel`).
+    /// Si un id aparece varias veces, la última aparición es la vigente.
+    pub fn insert_many_parallel(&mut self, vec_list: Vec<VFSVector>, threads: usize)
+    where
+        F: Sync,
+        R: Sync,
+    {
+        for v in &vec_list {
+            if let Some(old) = self.latest.remove(&v.id()) {
+                self.hnsw.mark_deleted(old);
+            }
+        }
+        let ids: Vec<u64> = vec_list.iter().map(|v| v.id()).collect();
+        let indexes = self.hnsw.insert_parallel(vec_list, threads);
+        for (id, index) in ids.into_iter().zip(indexes) {
+            if let Some(old) = self.latest.insert(id, index) {
+                self.hnsw.mark_deleted(old);
+            }
+        }
+        self.maybe_rebuild();
+    }
+
+    /// Devuelve hasta `k` vecinos de `vfs_vector`, ordenados de menor a mayor distancia.
+    /// `ef_search` es el tamaño de la lista de candidatos en la capa cero: más alto da más recall pero es más lento.
+    /// Nunca se usa un valor menor que `k`, o no habría candidatos suficientes.
+    /// Puede devolver menos de `k` si ni con `MAX_EF_SEARCH` candidatos se alcanzan `k` nodos vigentes.
+    pub fn query(&mut self, vfs_vector: &VFSVector, k: usize, ef_search: usize) -> io::Result<Vec<(Box<VFSVector>, f32)>> {
+        self.maybe_rebuild();
+        let mut ef = ef_search.max(k);
+        loop {
+            let results = self.query_with_ef(vfs_vector, k, ef);
+            // Si la búsqueda no alcanza `k` nodos vigentes (p. ej. zonas rodeadas de nodos borrados),
+            // se repite con más candidatos, sin pasar de `MAX_EF_SEARCH`.
+            if results.len() == k || results.len() == self.len() || ef >= self.hnsw.len() || ef >= MAX_EF_SEARCH {
+                return Ok(results);
+            }
+            ef = (ef * 2).min(MAX_EF_SEARCH);
+        }
+    }
+
+    fn query_with_ef(&self, vfs_vector: &VFSVector, k: usize, ef: usize) -> Vec<(Box<VFSVector>, f32)> {
+        // Inicializar output como un vector mutable.
         let mut output = vec![
             Neighbor {
                 index: !0,
                 distance: 0.0,
             };
-            1
+            ef
         ];
-    
-       
-    
+
         // Inicializar el searcher
         let mut searcher = self.get_searcher();
-    
+
         // Realizar la búsqueda
-        let mut result = self.hnsw.nearest(&vfs_vector, 24, &mut searcher, &mut output);
-        // Result es un array de Neighbors. Podemos obtener las features a partir de su índice, ya que el struct Neighbors contiene {index, distance}
-        // pero yo quiero el mapeo completo feature, distance.
-        // features es una propiedad de self.hnsw
-        
+        let result = self.hnsw.nearest(vfs_vector, ef, &mut searcher, &mut output);
+        // Result es un array de Neighbors ordenado por distancia. Podemos obtener las features a partir de su índice,
+        // ya que el struct Neighbors contiene {index, distance}, pero yo quiero el mapeo completo feature, distance.
+
         // Mapear los resultados para obtener (VFSVector, distance)
-        let mut mapped_results = Vec::with_capacity(output.len());
-    
-        for neighbor in output.iter() {
-            if neighbor.index != !0 {  // Verificar que el índice sea válido
+        let mut mapped_results = Vec::with_capacity(k.min(result.len()));
+
+        for neighbor in result.iter() {
+            if mapped_results.len() == k {
+                break;
+            }
+            if neighbor.index != !0 && self.is_live(neighbor.index) {  // Verificar que el índice sea válido y el vector siga vigente
                 // Obtener el vector de características correspondiente al índice
                 let feature = self.get_feature(neighbor.index);
-            
+
                 // Añadir el par (feature, distance) al resultado
-             mapped_results.push((feature, neighbor.distance));
+                mapped_results.push((feature, neighbor.distance));
             }
         }
-    
-    Ok(mapped_results)
-    
-    
-       
+
+        mapped_results
     }
 
-}
\ No newline at end of file
+}
+// ------------------------------------------------------------------------------------------------------------
+/*
+######  LSH  #####
+
+LSH (locality-sensitive hashing) es de la categoría de hashes: se usan funciones hash que, a diferencia de las normales,
+hacen que los vectores parecidos tengan más probabilidad de caer en el mismo cubo (bucket).
+
+Para la distancia coseno se usan hiperplanos aleatorios que pasan por el origen: cada bit de la firma de un vector indica
+a qué lado de un hiperplano queda. Dos vectores que forman un ángulo θ coinciden en un bit con probabilidad 1 - θ/π.
+
+- Con más `bits` por tabla los cubos son más pequeños: menos candidatos, pero más vecinos se quedan fuera.
+- Con varias `tables` independientes se compensa: un vector es candidato si comparte cubo con la consulta en alguna de ellas.
+- Multi-probe: además del cubo de la consulta se visitan `probes` cubos vecinos en cada tabla, los que resultan de cambiar
+  los bits cuyos hiperplanos pasan más cerca de la consulta (donde es más fácil que un vecino quede al otro lado).
+  Así se consigue el mismo recall con menos tablas.
+
+Insertar o borrar solo cuesta calcular las firmas, sin mantener enlaces como en HNSW.
+El índice solo devuelve candidatos: la distancia exacta se calcula después con los vectores.
+*/
+
+pub const DEFAULT_LSH_TABLES: usize = 8;
+pub const DEFAULT_LSH_BITS: usize = 12;
+pub const DEFAULT_LSH_PROBES: usize = 4;
+pub const MAX_LSH_TABLES: usize = 64;
+pub const MAX_LSH_BITS: usize = 64; // La firma de cada tabla es un u64.
+pub const MAX_LSH_PROBES: usize = 1024;
+const LSH_SEED: u64 = 0x15f;
+
+// Parámetros LSH de una colección.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LshParams {
+    pub tables: usize,
+    pub bits: usize,   // Bits de la firma de cada tabla (hiperplanos por tabla).
+    pub probes: usize, // Cubos vecinos que se visitan por tabla si la petición no lo indica.
+}
+
+impl Default for LshParams {
+    fn default() -> Self {
+        Self { tables: DEFAULT_LSH_TABLES, bits: DEFAULT_LSH_BITS, probes: DEFAULT_LSH_PROBES }
+    }
+}
+
+impl LshParams {
+    pub fn new(tables: Option<usize>, bits: Option<usize>, probes: Option<usize>) -> Result<Self, String> {
+        let params = Self {
+            tables: tables.unwrap_or(DEFAULT_LSH_TABLES),
+            bits: bits.unwrap_or(DEFAULT_LSH_BITS),
+            probes: probes.unwrap_or(DEFAULT_LSH_PROBES),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tables == 0 || self.tables > MAX_LSH_TABLES {
+            return Err(format!("tables must be between 1 and {}", MAX_LSH_TABLES));
+        }
+        if self.bits == 0 || self.bits > MAX_LSH_BITS {
+            return Err(format!("bits must be between 1 and {}", MAX_LSH_BITS));
+        }
+        validate_lsh_probes(self.probes)
+    }
+}
+
+pub fn validate_lsh_probes(probes: usize) -> Result<(), String> {
+    if probes > MAX_LSH_PROBES {
+        return Err(format!("probes must be at most {}", MAX_LSH_PROBES));
+    }
+    Ok(())
+}
+
+// Tamaño del índice LSH, para el endpoint de estadísticas.
+#[derive(Debug, Serialize)]
+pub struct LshStats {
+    pub tables: usize,
+    pub bits: usize,
+    pub vectors: usize,
+    pub buckets: usize, // Cubos no vacíos entre todas las tablas.
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LshIndex {
+    params: LshParams,
+    hyperplanes: Vec<Vec<Vec<f32>>>, // hyperplanes[tabla][bit]: normal del hiperplano.
+    buckets: Vec<HashMap<u64, Vec<u64>>>, // buckets[tabla][firma]: ids del cubo.
+    signatures: HashMap<u64, Vec<u64>>, // Firmas de cada id en cada tabla, para sacarlo de sus cubos.
+}
+
+impl LshIndex {
+    /// Índice vacío para vectores de `dimension` valores. Los hiperplanos salen de una semilla fija,
+    /// así que dos índices con los mismos parámetros y dimensión asignan los mismos cubos.
+    pub fn new(params: LshParams, dimension: usize) -> Self {
+        let mut rng = SmallRng::seed_from_u64(LSH_SEED);
+        let hyperplanes = (0..params.tables)
+            .map(|_| (0..params.bits)
+                .map(|_| (0..dimension).map(|_| gaussian(&mut rng)).collect())
+                .collect())
+            .collect();
+
+        Self {
+            params,
+            hyperplanes,
+            buckets: (0..params.tables).map(|_| HashMap::new()).collect(),
+            signatures: HashMap::new(),
+        }
+    }
+
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+
+    // Dimensión de los hiperplanos (0 si el índice se creó sin vectores).
+    pub fn dimension(&self) -> usize {
+        self.hyperplanes.first().and_then(|planes| planes.first()).map_or(0, Vec::len)
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    pub fn stats(&self) -> LshStats {
+        LshStats {
+            tables: self.params.tables,
+            bits: self.params.bits,
+            vectors: self.len(),
+            buckets: self.buckets.iter().map(HashMap::len).sum(),
+        }
+    }
+
+    /// Añade `id` al cubo de su firma en cada tabla. Si ya estaba (otra versión del vector), primero lo saca de los suyos.
+    pub fn insert(&mut self, id: u64, values: VectorView<'_>) {
+        self.remove(id);
+        let signatures: Vec<u64> = self.hyperplanes.iter()
+            .map(|planes| signature(&projections(planes, values)))
+            .collect();
+        for (table, &signature) in self.buckets.iter_mut().zip(&signatures) {
+            table.entry(signature).or_default().push(id);
+        }
+        self.signatures.insert(id, signatures);
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let Some(signatures) = self.signatures.remove(&id) else {
+            return;
+        };
+        for (table, signature) in self.buckets.iter_mut().zip(signatures) {
+            if let Some(ids) = table.get_mut(&signature) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    table.remove(&signature);
+                }
+            }
+        }
+    }
+
+    /// Ids del cubo de `values` y de sus `probes` cubos vecinos más probables en cada tabla, sin repetir.
+    pub fn candidates(&self, values: VectorView<'_>, probes: usize) -> Vec<u64> {
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+        for (planes, table) in self.hyperplanes.iter().zip(&self.buckets) {
+            for signature in probe_sequence(&projections(planes, values), probes) {
+                if let Some(ids) = table.get(&signature) {
+                    candidates.extend(ids.iter().filter(|&&id| seen.insert(id)));
+                }
+            }
+        }
+        candidates
+    }
+}
+
+// Producto escalar de `values` con la normal de cada hiperplano de una tabla.
+fn projections(planes: &[Vec<f32>], values: VectorView<'_>) -> Vec<f32> {
+    planes.iter()
+        .map(|plane| plane.iter().take(values.len()).enumerate().map(|(i, a)| a * values.get(i)).sum())
+        .collect()
+}
+
+// Bit `i` a 1 si el vector queda en el lado positivo del hiperplano `i`.
+fn signature(projections: &[f32]) -> u64 {
+    projections.iter().enumerate()
+        .filter(|(_, &p)| p >= 0.0)
+        .fold(0, |signature, (bit, _)| signature | (1 << bit))
+}
+
+// Firma de la consulta seguida de `probes` firmas vecinas, de más a menos probable (multi-probe LSH, Lv et al. 2007).
+// Cambiar un bit cuesta el valor absoluto de su proyección, y un conjunto de bits, la suma. Con los bits ordenados por coste,
+// cada conjunto {.., j} genera "shift" (cambiar j por j + 1) y "expand" (añadir j + 1), y así se recorren todos los
+// conjuntos una sola vez en orden de coste creciente empezando por {0}.
+fn probe_sequence(projections: &[f32], probes: usize) -> Vec<u64> {
+    let base = signature(projections);
+    let mut order: Vec<usize> = (0..projections.len()).collect();
+    order.sort_by(|&a, &b| projections[a].abs().partial_cmp(&projections[b].abs()).unwrap_or(std::cmp::Ordering::Equal));
+    let cost = |set: &Vec<usize>| set.iter().map(|&j| projections[order[j]].abs()).sum::<f32>();
+
+    let mut sequence = vec![base];
+    let mut frontier: Vec<Vec<usize>> = vec![vec![0]];
+    while sequence.len() <= probes && !frontier.is_empty() {
+        let next = frontier.iter()
+            .map(cost)
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map_or(0, |(ix, _)| ix);
+        let set = frontier.swap_remove(next);
+
+        let last = set[set.len() - 1];
+        if last + 1 < order.len() {
+            let mut shifted = set.clone();
+            shifted[set.len() - 1] = last + 1;
+            frontier.push(shifted);
+            let mut expanded = set.clone();
+            expanded.push(last + 1);
+            frontier.push(expanded);
+        }
+        sequence.push(set.iter().fold(base, |signature, &j| signature ^ (1 << order[j])));
+    }
+    sequence
+}
+
+// Muestra de una normal estándar (Box-Muller). Con normales independientes la dirección del hiperplano es uniforme.
+fn gaussian(rng: &mut SmallRng) -> f32 {
+    let u1: f32 = rng.random_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.random_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use super::vector::VFSVector;
+
+// Buffer pool de lectura.
+// Guarda los vectores leídos del archivo de datos, indexados por el offset de su registro, y expulsa
+// el menos usado recientemente (LRU) cuando se supera el presupuesto de bytes.
+// Es independiente de la memtable: solo se llena desde las lecturas y nunca escribe en disco.
+// Como el archivo es append-only, un offset siempre contiene el mismo registro, así que las entradas no caducan
+// (una nueva versión de un vector tiene otro offset). Solo hay que vaciarlo cuando el archivo se trunca o se compacta.
+
+pub const DEFAULT_CACHE_BYTES: usize = 4 * 1024 * 1024; // 4MB
+
+struct CacheEntry {
+    vector: VFSVector,
+    size: usize,   // Bytes que ocupa el registro en el archivo.
+    last_use: u64, // Momento del último acceso, clave en `recency`.
+}
+
+pub struct VectorCache {
+    capacity_bytes: usize, // 0 desactiva la caché.
+    used_bytes: usize,
+    entries: HashMap<usize, CacheEntry>, // offset -> vector
+    recency: BTreeMap<u64, usize>,       // último acceso -> offset. El primero es el menos usado recientemente.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+// Contadores de la caché, para el endpoint de estadísticas.
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub capacity_bytes: usize,
+    pub used_bytes: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl VectorCache {
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            used_bytes: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    // Busca el vector guardado en `offset` y lo marca como el más reciente.
+    pub fn get(&mut self, offset: usize) -> Option<VFSVector> {
+        self.clock += 1;
+        match self.entries.get_mut(&offset) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_use);
+                entry.last_use = self.clock;
+                self.recency.insert(self.clock, offset);
+                self.hits += 1;
+                Some(entry.vector.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Guarda el vector leído en `offset`. `size` es el tamaño de su registro.
+    // Los vectores más grandes que toda la caché no se guardan.
+    pub fn insert(&mut self, offset: usize, vector: VFSVector, size: usize) {
+        if size > self.capacity_bytes {
+            return;
+        }
+        self.remove(offset);
+
+        while self.used_bytes + size > self.capacity_bytes {
+            let Some((_, lru_offset)) = self.recency.pop_first() else { break };
+            if let Some(evicted) = self.entries.remove(&lru_offset) {
+                self.used_bytes -= evicted.size;
+                self.evictions += 1;
+            }
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, offset);
+        self.entries.insert(offset, CacheEntry { vector, size, last_use: self.clock });
+        self.used_bytes += size;
+    }
+
+    fn remove(&mut self, offset: usize) {
+        if let Some(entry) = self.entries.remove(&offset) {
+            self.recency.remove(&entry.last_use);
+            self.used_bytes -= entry.size;
+        }
+    }
+
+    // Vacía la caché (los offsets dejan de ser válidos). Los contadores se mantienen.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.used_bytes = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            capacity_bytes: self.capacity_bytes,
+            used_bytes: self.used_bytes,
+            entries: self.entries.len(),
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use serde::{Serialize, Deserialize};
+use bincode;
+use super::err::VFSError;
+
+// Cada colección guarda sus archivos en su propio directorio:
+// - data/<nombre>/vectors.dat          archivo de datos
+// - data/<nombre>/vectors.dat.compact  archivo temporal de la compactación
+// - data/<nombre>/ivf/index.bin        centroides del índice IVF y lista de cada vector
+// - data/<nombre>/ivf/list_<n>.ids     ids de los vectores de la lista del centroide n
+// - data/<nombre>/ivf_pq.bin           centroides, codebooks y códigos del índice IVF-PQ
+// - data/<nombre>/lsh.bin              hiperplanos y cubos del índice LSH
+// - data/<nombre>/vamana.graph         grafo Vamana (registros de tamaño fijo en bloques de 4 KB)
+// - data/<nombre>/vamana.bin           códigos PQ y cambios pendientes del índice Vamana
+// - state/<nombre>/vfs_state.bin       snapshot del índice
+// - state/<nombre>/vfs.wal             write-ahead log de la memtable
+// - state/<nombre>/hnsw.bin            grafo HNSW de la búsqueda aproximada
+// El catálogo (state/catalog.bin) guarda qué colecciones existen, para recuperarlas al arrancar.
+
+const DATA_DIR: &str = "data";
+const STATE_DIR: &str = "state";
+const CATALOG_PATH: &str = "state/catalog.bin";
+const MAX_NAME_LEN: usize = 64;
+
+// Rutas de los archivos de una colección.
+#[derive(Debug, Clone)]
+pub struct CollectionPaths {
+    pub data: String,
+    pub compaction: String,
+    pub state: String,
+    pub wal: String,
+    pub hnsw: String,
+    pub ivf: String,
+    pub ivf_pq: String,
+    pub lsh: String,
+    pub vamana: String,
+    pub vamana_graph: String,
+    data_dir: String,
+    state_dir: String,
+}
+
+impl CollectionPaths {
+    pub fn new(name: &str) -> Self {
+        let data_dir = format!("{}/{}", DATA_DIR, name);
+        let state_dir = format!("{}/{}", STATE_DIR, name);
+        Self {
+            data: format!("{}/vectors.dat", data_dir),
+            compaction: format!("{}/vectors.dat.compact", data_dir),
+            state: format!("{}/vfs_state.bin", state_dir),
+            wal: format!("{}/vfs.wal", state_dir),
+            hnsw: format!("{}/hnsw.bin", state_dir),
+            ivf: format!("{}/ivf", data_dir),
+            ivf_pq: format!("{}/ivf_pq.bin", data_dir),
+            lsh: format!("{}/lsh.bin", data_dir),
+            vamana: format!("{}/vamana.bin", data_dir),
+            vamana_graph: format!("{}/vamana.graph", data_dir),
+            data_dir,
+            state_dir,
+        }
+    }
+
+    // Borra todos los archivos de la colección.
+    pub fn remove_files(&self) -> io::Result<()> {
+        for dir in [&self.data_dir, &self.state_dir] {
+            match fs::remove_dir_all(dir) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// El nombre se usa como directorio, así que solo se admiten letras, números, '_' y '-'.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Colecciones existentes y la colección por defecto (la que usan las rutas sin `/collections/<nombre>`).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub collections: BTreeSet<String>,
+    pub default: Option<String>,
+}
+
+impl Catalog {
+    // Lee el catálogo. Si no existe se devuelve uno vacío.
+    pub fn load() -> Result<Self, VFSError> {
+        match fs::read(CATALOG_PATH) {
+            Ok(bytes) => bincode::deserialize(&bytes).map_err(|e| VFSError::SerializationError(e.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(VFSError::IoError(e)),
+        }
+    }
+
+    // Guarda el catálogo en un archivo temporal y lo renombra, para no dejarlo a medias.
+    pub fn save(&self) -> Result<(), VFSError> {
+        let encoded = bincode::serialize(self).map_err(|e| VFSError::SerializationError(e.to_string()))?;
+        fs::create_dir_all(STATE_DIR)?;
+
+        let tmp_path = format!("{}.tmp", CATALOG_PATH);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        file.write_all(&encoded)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, CATALOG_PATH)?;
+        Ok(())
+    }
+}
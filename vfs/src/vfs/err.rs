@@ -13,6 +13,10 @@ pub enum VFSError {
     InvalidVector(String),
     IdGenerationError(String),
     SerializationError(String),
+    NotFound(String),
+    CompactionError(String),
+    CorruptedData(usize, String), // Offset del registro dañado y motivo.
+    UnsupportedFormat(String),
     // Puedes añadir más variantes según necesites
 }
 
@@ -24,6 +28,10 @@ impl fmt::Display for VFSError {
             VFSError::InvalidVector(msg) => write!(f, "Invalid vector: {}", msg),
             VFSError::IdGenerationError(msg) => write!(f, "ID generation error: {}", msg),
             VFSError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            VFSError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            VFSError::CompactionError(msg) => write!(f, "Compaction error: {}", msg),
+            VFSError::CorruptedData(offset, msg) => write!(f, "Corrupted data at offset {}: {}", offset, msg),
+            VFSError::UnsupportedFormat(msg) => write!(f, "Unsupported data format: {}", msg),
         }
     }
 }
@@ -43,3 +51,13 @@ impl From<io::Error> for VFSError {
         VFSError::IoError(err)
     }
 }
+
+// Y al revés, para las funciones que devuelven io::Result. Los errores de E/S se devuelven tal cual.
+impl From<VFSError> for io::Error {
+    fn from(err: VFSError) -> Self {
+        match err {
+            VFSError::IoError(err) => err,
+            err => io::Error::other(err.to_string()),
+        }
+    }
+}
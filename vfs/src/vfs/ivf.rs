@@ -0,0 +1,405 @@
+// Índice IVF-Flat (inverted file).
+//
+// Los vectores se agrupan con k-means en `nlist` centroides y el id de cada vector se guarda en la lista
+// (posting list) de su centroide más cercano. Al buscar, solo se recorren las `nprobe` listas cuyos centroides están más cerca
+// de la consulta, y dentro de ellas se calcula la distancia exacta a cada vector ("flat": sin comprimir).
+//
+// Con nprobe = nlist la búsqueda es exacta. Cuanto menor es nprobe, más rápida y menos recall.
+// Los centroides se entrenan una vez; después los vectores nuevos se asignan al centroide más cercano sin
+// moverlos. Si la colección crece mucho desde el entrenamiento, el manager vuelve a entrenar el índice.
+// Las listas se guardan en disco, una por centroide, con los ids de sus vectores (ver `PostingFiles`).
+// Los centroides (`IvfCentroids`) se comparten con el índice IVF-PQ (`pq.rs`), que guarda en memoria códigos PQ.
+//
+// Con el producto escalar, k-means y el reparto de los vectores en listas usan la distancia euclídea (el producto
+// escalar no es una distancia: favorece a los centroides de mayor norma). Solo la elección de las listas que se
+// recorren usa el producto escalar con la consulta, que es la media del producto con los vectores de la lista.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+use super::vector::{VFSVector, VectorView};
+use super::rank::{DistanceMethod, view_distance};
+
+pub const DEFAULT_NPROBE: usize = 8;
+pub const MAX_NLIST: usize = 65_536;
+const KMEANS_ITERATIONS: usize = 20;
+const MAX_TRAINING_POINTS: usize = 100_000; // Con más vectores, k-means se entrena con una muestra.
+const KMEANS_SEED: u64 = 0x1f5;
+const INDEX_FILE: &str = "index.bin"; // Centroides y `assigned`, en el directorio del índice junto a las listas.
+const ID_SIZE: usize = std::mem::size_of::<u64>();
+
+// Parámetros IVF de una colección.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IvfParams {
+    pub nlist: Option<usize>, // Número de listas. Sin valor se usa la raíz cuadrada del número de vectores al entrenar.
+    pub nprobe: usize,        // Listas que se recorren por búsqueda si la petición no lo indica.
+    pub pq_m: Option<usize>,  // Subcuantizadores del índice IVF-PQ. Sin valor se eligen según la dimensión.
+}
+
+impl Default for IvfParams {
+    fn default() -> Self {
+        Self { nlist: None, nprobe: DEFAULT_NPROBE, pq_m: None }
+    }
+}
+
+impl IvfParams {
+    pub fn new(nlist: Option<usize>, nprobe: Option<usize>, pq_m: Option<usize>) -> Result<Self, String> {
+        let params = Self { nlist, nprobe: nprobe.unwrap_or(DEFAULT_NPROBE), pq_m };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if matches!(self.nlist, Some(nlist) if nlist == 0 || nlist > MAX_NLIST) {
+            return Err(format!("nlist must be between 1 and {}", MAX_NLIST));
+        }
+        validate_nprobe(self.nprobe)
+    }
+
+    // Listas para una colección de `len` vectores.
+    fn nlist_for(&self, len: usize) -> usize {
+        let nlist = self.nlist.unwrap_or_else(|| (len as f64).sqrt().round() as usize);
+        nlist.clamp(1, len.max(1))
+    }
+}
+
+pub fn validate_nprobe(nprobe: usize) -> Result<(), String> {
+    if nprobe == 0 || nprobe > MAX_NLIST {
+        return Err(format!("nprobe must be between 1 and {}", MAX_NLIST));
+    }
+    Ok(())
+}
+
+// Tamaño del índice, para el endpoint de estadísticas.
+#[derive(Debug, Serialize)]
+pub struct IvfStats {
+    pub lists: usize,
+    pub vectors: usize,
+    pub trained_vectors: usize,
+}
+
+// Listas invertidas en disco, junto al archivo de datos: un archivo por centroide (`list_<n>.ids`) con los ids
+// de sus vectores (u64 LE) en orden de inserción. Los vectores no se copian: la búsqueda los lee del manager.
+// Los archivos solo crecen: si un vector se borra o pasa a otra lista, su id se queda en la lista antigua y la
+// búsqueda lo descarta con `assigned`. Al volver a entrenar el índice se reescriben todas.
+#[derive(Serialize, Deserialize)]
+struct PostingFiles {
+    dir: String,
+}
+
+impl PostingFiles {
+    fn path(&self, list: usize) -> String {
+        format!("{}/list_{}.ids", self.dir, list)
+    }
+
+    // Sustituye todas las listas por `lists`.
+    fn rewrite(&self, lists: &[Vec<u64>]) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        fs::create_dir_all(&self.dir)?;
+        for (list, ids) in lists.iter().enumerate() {
+            let bytes: Vec<u8> = ids.iter().flat_map(|id| id.to_le_bytes()).collect();
+            fs::write(self.path(list), bytes)?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, list: usize, id: u64) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(self.path(list))?;
+        file.write_all(&id.to_le_bytes())
+    }
+
+    // Ids de una lista, incluidos los que ya no están en ella. Si se cortó una escritura, se ignora el id a medias.
+    fn read(&self, list: usize) -> io::Result<Vec<u64>> {
+        let bytes = match fs::read(self.path(list)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(bytes.chunks_exact(ID_SIZE)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+}
+
+// Centroides de un índice IVF y lista donde está la versión vigente de cada id.
+// Los usan tanto el IVF-Flat, con las listas en disco, como el IVF-PQ, con las listas de códigos en memoria.
+#[derive(Serialize, Deserialize)]
+pub(super) struct IvfCentroids {
+    distance_method: DistanceMethod,
+    centroids: Vec<Vec<f32>>,
+    assigned: HashMap<u64, usize>, // Lista donde está la versión vigente de cada id.
+    trained_len: usize,            // Vectores que había al entrenar.
+    requested_nlist: Option<usize>, // `nlist` de los parámetros con los que se entrenó.
+}
+
+impl IvfCentroids {
+    /// Entrena los centroides con k-means sobre `values` (sin asignar ningún vector todavía).
+    pub(super) fn train(distance_method: DistanceMethod, values: &[Vec<f32>], params: &IvfParams) -> Self {
+        let nlist = params.nlist_for(values.len());
+        let centroids = if values.is_empty() {
+            Vec::new()
+        } else {
+            kmeans(&partition_method(&distance_method), &training_sample(values, MAX_TRAINING_POINTS), nlist)
+        };
+
+        Self {
+            distance_method,
+            centroids,
+            assigned: HashMap::new(),
+            trained_len: values.len(),
+            requested_nlist: params.nlist,
+        }
+    }
+
+    // Número de listas.
+    pub(super) fn lists(&self) -> usize {
+        self.centroids.len()
+    }
+
+    pub(super) fn distance_method(&self) -> DistanceMethod {
+        self.distance_method
+    }
+
+    pub(super) fn trained_len(&self) -> usize {
+        self.trained_len
+    }
+
+    // Si se entrenó con el mismo `nlist`. `nprobe` solo afecta a las búsquedas.
+    pub(super) fn trained_with(&self, params: &IvfParams) -> bool {
+        self.requested_nlist == params.nlist
+    }
+
+    // Vectores vigentes en el índice.
+    pub(super) fn len(&self) -> usize {
+        self.assigned.len()
+    }
+
+    pub(super) fn stats(&self) -> IvfStats {
+        IvfStats {
+            lists: self.lists(),
+            vectors: self.len(),
+            trained_vectors: self.trained_len,
+        }
+    }
+
+    /// Asigna `id` a la lista del centroide más cercano a `values` y la devuelve.
+    /// `None` si no hay centroides (el índice se entrenó sin vectores).
+    pub(super) fn assign(&mut self, id: u64, values: VectorView<'_>) -> Option<usize> {
+        if self.centroids.is_empty() {
+            return None;
+        }
+        let list = nearest_centroid(&partition_method(&self.distance_method), &self.centroids, values);
+        self.assigned.insert(id, list);
+        Some(list)
+    }
+
+    // Quita `id` del índice y devuelve la lista donde estaba.
+    pub(super) fn remove(&mut self, id: u64) -> Option<usize> {
+        self.assigned.remove(&id)
+    }
+
+    // Si la versión vigente de `id` está en `list`.
+    pub(super) fn is_assigned(&self, id: u64, list: usize) -> bool {
+        self.assigned.get(&id) == Some(&list)
+    }
+
+    // Las `nprobe` listas cuyos centroides están más cerca de `values`, de menor a mayor distancia.
+    pub(super) fn probes(&self, values: VectorView<'_>, nprobe: usize) -> Vec<usize> {
+        nearest_centroids(&self.distance_method, &self.centroids, values, nprobe)
+    }
+}
+
+// Índice IVF-Flat: centroides y listas de ids en disco. Lo que se guarda en `index.bin` son los centroides;
+// las listas están en sus propios archivos.
+#[derive(Serialize, Deserialize)]
+pub struct IvfIndex {
+    centroids: IvfCentroids,
+    lists: PostingFiles,
+}
+
+impl IvfIndex {
+    /// Entrena los centroides con k-means y escribe en `dir` la lista de cada centroide con los ids de `vectors`.
+    pub fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &IvfParams, dir: &str) -> io::Result<Self> {
+        let values: Vec<Vec<f32>> = vectors.iter().map(|v| v.as_f32_vec()).collect();
+        let mut centroids = IvfCentroids::train(distance_method, &values, params);
+
+        let mut lists = vec![Vec::new(); centroids.lists()];
+        for (vector, values) in vectors.iter().zip(&values) {
+            if let Some(list) = centroids.assign(vector.id(), values.into()) {
+                lists[list].push(vector.id());
+            }
+        }
+        let files = PostingFiles { dir: dir.to_string() };
+        files.rewrite(&lists)?;
+
+        Ok(Self { centroids, lists: files })
+    }
+
+    // Archivo de los centroides del índice guardado en `dir`.
+    pub fn index_path(dir: &str) -> String {
+        format!("{}/{}", dir, INDEX_FILE)
+    }
+
+    /// Borra el índice guardado en `dir`, con sus listas.
+    pub fn remove_files(dir: &str) -> io::Result<()> {
+        match fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn distance_method(&self) -> DistanceMethod {
+        self.centroids.distance_method()
+    }
+
+    pub fn trained_len(&self) -> usize {
+        self.centroids.trained_len()
+    }
+
+    pub fn trained_with(&self, params: &IvfParams) -> bool {
+        self.centroids.trained_with(params)
+    }
+
+    pub fn len(&self) -> usize {
+        self.centroids.len()
+    }
+
+    pub fn stats(&self) -> IvfStats {
+        self.centroids.stats()
+    }
+
+    /// Añade el id de un vector a la lista de su centroide más cercano. Si ya había una versión con el mismo id, la sustituye.
+    pub fn insert(&mut self, vector: &VFSVector) -> io::Result<()> {
+        if let Some(list) = self.centroids.assign(vector.id(), vector.view()) {
+            self.lists.append(list, vector.id())?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.centroids.remove(id);
+    }
+
+    /// Ids vigentes de las `nprobe` listas más cercanas a `query`, sin repetir.
+    /// Las distancias las calcula el manager, que es quien lee los vectores.
+    pub fn candidates(&self, query: &VFSVector, nprobe: usize) -> io::Result<Vec<u64>> {
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+        for list in self.centroids.probes(query.view(), nprobe) {
+            for id in self.lists.read(list)? {
+                if self.centroids.is_assigned(id, list) && seen.insert(id) {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+}
+
+// Métrica con la que se forman las listas.
+fn partition_method(distance_method: &DistanceMethod) -> DistanceMethod {
+    match distance_method {
+        DistanceMethod::DotProduct | DistanceMethod::SimdDotProduct => DistanceMethod::Euclidean,
+        method => *method,
+    }
+}
+
+// Como mucho `max` puntos repartidos uniformemente (cada `step`), para que k-means no tarde demasiado.
+pub(super) fn training_sample(values: &[Vec<f32>], max: usize) -> Vec<Vec<f32>> {
+    let step = values.len().div_ceil(max).max(1);
+    values.iter().step_by(step).cloned().collect()
+}
+
+// Índice del centroide más cercano a `values`.
+pub(super) fn nearest_centroid(distance_method: &DistanceMethod, centroids: &[Vec<f32>], values: VectorView<'_>) -> usize {
+    centroids.iter()
+        .map(|centroid| slice_distance(distance_method, values, centroid))
+        .enumerate()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map_or(0, |(ix, _)| ix)
+}
+
+// Índices de los `n` centroides más cercanos a `values`, de menor a mayor distancia.
+fn nearest_centroids(distance_method: &DistanceMethod, centroids: &[Vec<f32>], values: VectorView<'_>, n: usize) -> Vec<usize> {
+    let mut distances: Vec<(usize, f32)> = centroids.iter()
+        .enumerate()
+        .map(|(ix, centroid)| (ix, slice_distance(distance_method, values, centroid)))
+        .collect();
+    distances.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    distances.into_iter().take(n).map(|(ix, _)| ix).collect()
+}
+
+// Distancia entre unos valores y una lista de f32 (un centroide o un vector guardado) con la métrica del índice.
+// Los centroides no son vectores de la colección, así que no se pasan a `VFSVector`.
+pub(super) fn slice_distance(distance_method: &DistanceMethod, a: VectorView<'_>, b: &[f32]) -> f32 {
+    view_distance(distance_method, a, b.into())
+}
+
+/// k-means con inicialización k-means++: cada centroide inicial se elige con probabilidad proporcional
+/// al cuadrado de su distancia al centroide más cercano ya elegido, lo que evita empezar con centroides amontonados.
+/// Los centroides de grupos que se quedan vacíos no se mueven.
+pub(super) fn kmeans(distance_method: &DistanceMethod, points: &[Vec<f32>], k: usize) -> Vec<Vec<f32>> {
+    let mut rng = SmallRng::seed_from_u64(KMEANS_SEED);
+    let k = k.min(points.len());
+
+    let mut centroids = vec![points[rng.random_range(0..points.len())].clone()];
+    let mut closest: Vec<f32> = points.iter()
+        .map(|p| slice_distance(distance_method, p.into(), &centroids[0]).powi(2))
+        .collect();
+    while centroids.len() < k {
+        let total: f32 = closest.iter().sum();
+        let next = if total > 0.0 {
+            let mut target = rng.random_range(0.0..total);
+            closest.iter().position(|&d| {
+                target -= d;
+                target < 0.0
+            }).unwrap_or(points.len() - 1)
+        } else {
+            // Todos los puntos coinciden con algún centroide: cualquiera vale.
+            rng.random_range(0..points.len())
+        };
+        centroids.push(points[next].clone());
+        for (d, p) in closest.iter_mut().zip(points) {
+            *d = d.min(slice_distance(distance_method, p.into(), &centroids[centroids.len() - 1]).powi(2));
+        }
+    }
+
+    let dimension = points[0].len();
+    let mut assignment = vec![usize::MAX; points.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        // Asignar cada punto a su centroide más cercano.
+        let mut changed = false;
+        for (p, assigned) in points.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_centroid(distance_method, &centroids, p.into());
+            if *assigned != nearest {
+                *assigned = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        // Mover cada centroide a la media de sus puntos.
+        let mut sums = vec![vec![0.0f32; dimension]; k];
+        let mut counts = vec![0usize; k];
+        for (p, &c) in points.iter().zip(&assignment) {
+            counts[c] += 1;
+            for (s, x) in sums[c].iter_mut().zip(p) {
+                *s += x;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = sum.into_iter().map(|s| s / count as f32).collect();
+            }
+        }
+    }
+    centroids
+}
@@ -2,6 +2,12 @@ pub mod serializer;  // Módulo para la serialización de los vectores
 pub mod vector;      // Módulo para las operaciones relacionadas con los vectores
 pub mod rank; // Módulo para la clase Ranker
 pub mod ann; // Búsquedas aproximadas (HNSW).
+pub mod ivf; // Búsquedas aproximadas con listas invertidas (IVF-Flat).
+pub mod pq; // Cuantización por producto e índice IVF-PQ.
+pub mod vamana; // Grafo Vamana en disco (estilo DiskANN).
 pub mod storage_manager; // VFSManager
 pub mod err;
+pub mod wal; // Write-ahead log de la memtable
+pub mod cache; // Buffer pool de lectura (LRU)
+pub mod collection; // Colecciones: rutas de sus archivos y catálogo
 pub mod tcp; // api
\ No newline at end of file
@@ -0,0 +1,259 @@
+// Cuantización por producto (PQ) e índice IVF-PQ.
+//
+// El codec divide cada vector en `m` subvectores de `dimensión / m` valores y entrena con k-means un codebook de
+// hasta 256 centroides por subespacio. Un vector se guarda como `m` bytes: el centroide más cercano en cada subespacio.
+// Para buscar se calcula una vez la tabla de distancias entre la consulta y todos los centroides de cada subespacio
+// (distancia asimétrica: la consulta no se comprime), y la distancia a cada código es la suma de `m` entradas de la tabla.
+// La tabla guarda lo que aporta cada subespacio a la distancia: la distancia euclídea al cuadrado, el producto escalar
+// cambiado de signo, la suma de diferencias absolutas (elevadas a `p` con Minkowski) o los bits distintos.
+// Chebyshev y Jaccard no se descomponen en una suma por subespacios, así que IVF-PQ no las admite.
+//
+// El índice IVF-PQ reparte los códigos en listas con los mismos centroides que el índice IVF, pero las guarda en
+// memoria: cada código ocupa `m` bytes, mucho menos que el vector. Como la distancia PQ es aproximada,
+// el manager puede recalcular la distancia exacta de los mejores candidatos leyendo los vectores originales.
+
+use serde::{Serialize, Deserialize};
+use super::vector::{VFSVector, VectorView};
+use super::rank::{DistanceMethod, manhattan, hamming};
+use super::ivf::{IvfCentroids, IvfParams, IvfStats, kmeans, nearest_centroid, training_sample};
+
+const CODEBOOK_SIZE: usize = 256; // Centroides por subespacio: cada uno se codifica en un byte.
+const MAX_TRAINING_POINTS: usize = 10_000; // Los codebooks se entrenan con una muestra.
+
+// Subcuantizadores por defecto: el mayor divisor de la dimensión que deja subvectores de al menos 4 valores.
+pub fn default_subquantizers(dimension: usize) -> usize {
+    let max = (dimension / 4).max(1);
+    (1..=max).rev().find(|m| dimension.is_multiple_of(*m)).unwrap_or(1)
+}
+
+pub fn validate_subquantizers(m: usize, dimension: usize) -> Result<(), String> {
+    if m == 0 || m > dimension || !dimension.is_multiple_of(m) {
+        return Err(format!("pq_m must be a divisor of the vector dimension ({})", dimension));
+    }
+    Ok(())
+}
+
+// Codebooks de cada subespacio.
+#[derive(Serialize, Deserialize)]
+pub struct PqCodec {
+    m: usize,    // Número de subespacios (bytes por código).
+    dsub: usize, // Valores de cada subvector.
+    codebooks: Vec<Vec<Vec<f32>>>, // codebooks[subespacio][centroide]
+}
+
+impl PqCodec {
+    /// Entrena un codebook por subespacio con k-means (distancia euclídea) sobre una muestra de `points`.
+    pub fn train(points: &[Vec<f32>], m: usize) -> Self {
+        let dimension = points.first().map_or(0, Vec::len);
+        let dsub = dimension / m.max(1);
+        let sample = training_sample(points, MAX_TRAINING_POINTS);
+        let codebooks = if sample.is_empty() {
+            Vec::new()
+        } else {
+            (0..m).map(|sub| {
+                let subvectors: Vec<Vec<f32>> = sample.iter()
+                    .map(|p| p[sub * dsub..(sub + 1) * dsub].to_vec())
+                    .collect();
+                kmeans(&DistanceMethod::Euclidean, &subvectors, CODEBOOK_SIZE)
+            }).collect()
+        };
+        Self { m, dsub, codebooks }
+    }
+
+    pub fn subquantizers(&self) -> usize {
+        self.m
+    }
+
+    // Un byte por subespacio: el índice del centroide más cercano.
+    pub fn encode(&self, values: VectorView<'_>) -> Vec<u8> {
+        self.codebooks.iter().enumerate()
+            .map(|(sub, codebook)| {
+                let subvector = values.slice(sub * self.dsub..(sub + 1) * self.dsub);
+                nearest_centroid(&DistanceMethod::Euclidean, codebook, subvector) as u8
+            })
+            .collect()
+    }
+
+    // `term` aplicado a cada subvector de `query` y cada centroide de su subespacio.
+    fn table<F: Fn(VectorView<'_>, &[f32]) -> f32>(&self, query: VectorView<'_>, term: F) -> Vec<Vec<f32>> {
+        self.codebooks.iter().enumerate()
+            .map(|(sub, codebook)| {
+                let subvector = query.slice(sub * self.dsub..(sub + 1) * self.dsub);
+                codebook.iter().map(|centroid| term(subvector, centroid)).collect()
+            })
+            .collect()
+    }
+
+    // Tabla de la consulta según la métrica. Las métricas que no se descomponen por subespacios usan la distancia
+    // euclídea al cuadrado, que solo sirve para orientar la búsqueda (el grafo Vamana).
+    pub fn query_table(&self, distance_method: &DistanceMethod, query: VectorView<'_>) -> Vec<Vec<f32>> {
+        match distance_method {
+            DistanceMethod::DotProduct | DistanceMethod::SimdDotProduct => {
+                self.table(query, |q, c| -c.iter().enumerate().map(|(i, y)| q.get(i) * y).sum::<f32>())
+            }
+            DistanceMethod::Manhattan | DistanceMethod::SimdManhattan => self.table(query, |q, c| manhattan(q, c.into())),
+            DistanceMethod::Minkowski(p) => {
+                self.table(query, |q, c| c.iter().enumerate().map(|(i, y)| (q.get(i) - y).abs().powf(*p)).sum())
+            }
+            DistanceMethod::Hamming | DistanceMethod::SimdHamming => self.table(query, |q, c| hamming(q, c.into())),
+            _ => self.table(query, |q, c| c.iter().enumerate().map(|(i, y)| (q.get(i) - y) * (q.get(i) - y)).sum()),
+        }
+    }
+
+    // Suma de las entradas de `table` que corresponden al vector codificado en `code`.
+    pub fn table_distance(table: &[Vec<f32>], code: &[u8]) -> f32 {
+        table.iter().zip(code).map(|(distances, &c)| distances[c as usize]).sum()
+    }
+}
+
+// Código PQ de un vector, guardado en su lista IVF.
+#[derive(Serialize, Deserialize)]
+struct PqCode {
+    id: u64,
+    code: Vec<u8>,
+}
+
+// Tamaño del índice IVF-PQ, para el endpoint de estadísticas.
+#[derive(Debug, Serialize)]
+pub struct IvfPqStats {
+    #[serde(flatten)]
+    pub ivf: IvfStats,
+    pub subquantizers: usize,
+    pub code_bytes: usize, // Bytes de los códigos de todos los vectores.
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IvfPqIndex {
+    ivf: IvfCentroids,
+    lists: Vec<Vec<PqCode>>,
+    codec: PqCodec,
+    requested_m: Option<usize>, // `pq_m` de los parámetros con los que se entrenó.
+}
+
+impl IvfPqIndex {
+    /// Entrena los centroides IVF y los codebooks PQ con `vectors` y los codifica en sus listas.
+    pub fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &IvfParams) -> Self {
+        let ids: Vec<u64> = vectors.iter().map(VFSVector::id).collect();
+        let values: Vec<Vec<f32>> = vectors.iter().map(|v| prepare(&distance_method, v.as_f32_vec())).collect();
+        let m = params.pq_m.unwrap_or_else(|| default_subquantizers(values.first().map_or(1, Vec::len)));
+
+        let ivf = IvfCentroids::train(distance_method, &values, params);
+        let mut index = Self {
+            lists: (0..ivf.lists()).map(|_| Vec::new()).collect(),
+            ivf,
+            codec: PqCodec::train(&values, m),
+            requested_m: params.pq_m,
+        };
+        for (id, values) in ids.into_iter().zip(values) {
+            index.insert_values(id, (&values).into());
+        }
+        index
+    }
+
+    pub fn distance_method(&self) -> DistanceMethod {
+        self.ivf.distance_method()
+    }
+
+    pub fn trained_len(&self) -> usize {
+        self.ivf.trained_len()
+    }
+
+    pub fn trained_with(&self, params: &IvfParams) -> bool {
+        self.ivf.trained_with(params) && self.requested_m == params.pq_m
+    }
+
+    pub fn len(&self) -> usize {
+        self.ivf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> IvfPqStats {
+        let ivf = self.ivf.stats();
+        IvfPqStats {
+            subquantizers: self.codec.subquantizers(),
+            code_bytes: ivf.vectors * self.codec.subquantizers(),
+            ivf,
+        }
+    }
+
+    /// Codifica el vector y lo añade a la lista de su centroide más cercano, sustituyendo la versión anterior.
+    pub fn insert(&mut self, vector: &VFSVector) {
+        let normalized = normalized(&self.distance_method(), vector.view());
+        let values = normalized.as_deref().map_or(vector.view(), VectorView::from);
+        self.insert_values(vector.id(), values);
+    }
+
+    fn insert_values(&mut self, id: u64, values: VectorView<'_>) {
+        self.remove(id);
+        if let Some(list) = self.ivf.assign(id, values) {
+            self.lists[list].push(PqCode { id, code: self.codec.encode(values) });
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        if let Some(list) = self.ivf.remove(id) {
+            self.lists[list].retain(|posting| posting.id != id);
+        }
+    }
+
+    /// Devuelve hasta `k` vecinos de `query` en las `nprobe` listas más cercanas, con distancias aproximadas (PQ).
+    pub fn query(&self, query: &VFSVector, k: usize, nprobe: usize) -> Vec<(u64, f32)> {
+        let distance_method = self.distance_method();
+        let normalized = normalized(&distance_method, query.view());
+        let values = normalized.as_deref().map_or(query.view(), VectorView::from);
+        let table = self.codec.query_table(&distance_method, values);
+        let mut results: Vec<(u64, f32)> = self.ivf.probes(values, nprobe).into_iter()
+            .flat_map(|list| self.lists[list].iter())
+            .map(|posting| {
+                let sum = PqCodec::table_distance(&table, &posting.code);
+                let distance = match distance_method {
+                    // Entre vectores unitarios, |a - b|² = 2 - 2·cos(a, b), así que la distancia coseno es |a - b|² / 2.
+                    DistanceMethod::Cosine | DistanceMethod::SimdCosine => sum / 2.0,
+                    DistanceMethod::DotProduct | DistanceMethod::SimdDotProduct
+                    | DistanceMethod::Manhattan | DistanceMethod::SimdManhattan
+                    | DistanceMethod::Hamming | DistanceMethod::SimdHamming => sum,
+                    DistanceMethod::Minkowski(p) => sum.powf(1.0 / p),
+                    DistanceMethod::Euclidean | DistanceMethod::SimdEuclidean => sum.sqrt(),
+                    // No se descomponen por subespacios: el ranker las rechaza antes de llegar aquí.
+                    DistanceMethod::Chebyshev | DistanceMethod::SimdChebyshev
+                    | DistanceMethod::Jaccard | DistanceMethod::SimdJaccard => sum.sqrt(),
+                };
+                (posting.id, distance)
+            })
+            .collect();
+        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(k);
+        results
+    }
+}
+
+// Si IVF-PQ puede calcular la métrica con los códigos.
+pub fn supports_distance(distance_method: &DistanceMethod) -> bool {
+    !matches!(distance_method,
+        DistanceMethod::Chebyshev | DistanceMethod::SimdChebyshev | DistanceMethod::Jaccard | DistanceMethod::SimdJaccard)
+}
+
+// Con la distancia coseno los vectores se normalizan antes de codificarlos, así la distancia euclídea de PQ sirve para las dos.
+pub(super) fn prepare(distance_method: &DistanceMethod, mut values: Vec<f32>) -> Vec<f32> {
+    if let Some(norm) = cosine_norm(distance_method, (&values).into()) {
+        values.iter_mut().for_each(|x| *x /= norm);
+    }
+    values
+}
+
+// Como `prepare`, pero sin copiar los valores si no hay que normalizarlos: en ese caso devuelve `None` y se usa la vista.
+pub(super) fn normalized(distance_method: &DistanceMethod, values: VectorView<'_>) -> Option<Vec<f32>> {
+    cosine_norm(distance_method, values).map(|norm| (0..values.len()).map(|i| values.get(i) / norm).collect())
+}
+
+// Norma entre la que hay que dividir los valores: solo con la distancia coseno y si no es cero.
+fn cosine_norm(distance_method: &DistanceMethod, values: VectorView<'_>) -> Option<f32> {
+    if !matches!(distance_method, DistanceMethod::Cosine | DistanceMethod::SimdCosine) {
+        return None;
+    }
+    let norm = (0..values.len()).map(|i| values.get(i) * values.get(i)).sum::<f32>().sqrt();
+    (norm > 0.0).then_some(norm)
+}
@@ -1,89 +1,239 @@
 
 use super::serializer::load_vectors;
-use super::vector::VFSVector;
+use super::vector::{VFSVector, VectorView};
 use super::storage_manager::{VFSManager, ResetOptions};
 
 use std::io;
-use std::simd::num::SimdFloat;
+use std::simd::Simd;
+use std::simd::num::{SimdFloat, SimdInt};
+use std::simd::cmp::SimdPartialOrd;
 use super::ann::VFSANNIndex;
+use super::pq::supports_distance;
 use std::collections::HashMap;
 use rand::rngs::SmallRng;
+use serde::{Serialize, Deserialize};
+
+
+// Kernels SIMD por bloques.
+// Los vectores se recorren en bloques de `SIMD_LANES` valores que se operan en paralelo, acumulando en un registro SIMD,
+// y los valores que sobran al final (la cola, menos de un bloque) se procesan uno a uno. Así cualquier dimensión
+// usa SIMD, no solo las que coinciden con un número de carriles soportado.
+// Trabajan sobre vistas prestadas: los vectores cuantizados se descuantizan bloque a bloque en el registro,
+// sin crear una copia en f32.
+
+const SIMD_LANES: usize = 16; // Un registro AVX-512 o dos AVX2/NEON de f32.
+type Lanes = Simd<f32, SIMD_LANES>;
+
+// Llama a `f` con cada par de bloques de las dos vistas. La forma de cargar cada vista (copiar los f32 o
+// descuantizar los i8) se elige una vez, fuera del bucle, para que el compilador genere un bucle para cada caso.
+#[inline(always)]
+fn zip_blocks<F: FnMut(Lanes, Lanes)>(a: VectorView, b: VectorView, f: F) {
+    match (a, b) {
+        (VectorView::Dense(x), VectorView::Dense(y)) => zip_loaded(x, y, load_dense, load_dense, f),
+        (VectorView::Dense(x), VectorView::Quantized(y, scale)) => zip_loaded(x, y, load_dense, load_quantized(scale), f),
+        (VectorView::Quantized(x, scale), VectorView::Dense(y)) => zip_loaded(x, y, load_quantized(scale), load_dense, f),
+        (VectorView::Quantized(x, scale_x), VectorView::Quantized(y, scale_y)) => {
+            zip_loaded(x, y, load_quantized(scale_x), load_quantized(scale_y), f)
+        }
+    }
+}
 
+#[inline(always)]
+fn zip_loaded<A, B, LA, LB, F>(a: &[A], b: &[B], load_a: LA, load_b: LB, mut f: F)
+where
+    LA: Fn(&[A]) -> Lanes,
+    LB: Fn(&[B]) -> Lanes,
+    F: FnMut(Lanes, Lanes),
+{
+    for (x, y) in a.chunks_exact(SIMD_LANES).zip(b.chunks_exact(SIMD_LANES)) {
+        f(load_a(x), load_b(y));
+    }
+}
 
-// macro para calcular la distancia euclidea simd.
-macro_rules! dynamic_simd_euclidean {
-    ($vec1:expr, $vec2:expr, [$( $lanes:literal ),*]) => {{
-        let len = $vec1.as_f32_vec().len();
-        let mut result: Option<f32> = None;
-
-        $(
-            if len == $lanes { // $lanes debe estar entre los valores permitidos  de LaneCount en core::Simd
-                // Link:  https://doc.rust-lang.org/std/simd/trait.SupportedLaneCount.html
-                if let (Some(simd1), Some(simd2)) =
-                    ($vec1.as_simd::<$lanes>(), $vec2.as_simd::<$lanes>())
-                {
-                    let diff = simd1 - simd2; // Restamos los vectores en paralelo
-                    result = Some((diff * diff).reduce_sum().sqrt()); // Suma de productos y raíz cuadrada.
-                }
-            }
-        )*
-
-        result.unwrap_or_else(|| panic!("No se puede usar SIMD con longitud {len}"))
-    }};
-}
-
-// Macro para calcular la distancia coseno simd.
-macro_rules! dynamic_simd_cosine {
-    ($vec1:expr, $vec2:expr, [$( $lanes:literal ),*]) => {{
-        let len = $vec1.as_f32_vec().len();
-        let mut result: Option<f32> = None;
-
-        $(
-            if len == $lanes {
-                if let (Some(simd1), Some(simd2)) =
-                    ($vec1.as_simd::<$lanes>(), $vec2.as_simd::<$lanes>())
-                {
-                    let dot = (simd1 * simd2).reduce_sum(); // Producto vectorial
-                    let norm1 = (simd1 * simd1).reduce_sum().sqrt(); // Norma del primer vector
-                    let norm2 = (simd2 * simd2).reduce_sum().sqrt(); // Norma del segundo
-                    result = Some(1.0 - (dot / (norm1 * norm2))); // Distancia coseno SIMD, calculada como 1 - similitud coseno
-                }
-            }
-        )*
+#[inline(always)]
+fn load_dense(values: &[f32]) -> Lanes {
+    Lanes::from_slice(values)
+}
+
+// Se multiplica por el inverso de la escala, que se calcula una sola vez.
+#[inline(always)]
+fn load_quantized(scale_factor: f32) -> impl Fn(&[i8]) -> Lanes {
+    let inverse = Lanes::splat(scale_factor.recip());
+    move |values| Simd::<i8, SIMD_LANES>::from_slice(values).cast::<f32>() * inverse
+}
+
+// Igual que `zip_blocks`, pero valor a valor. Lo usan las métricas escalares.
+#[inline(always)]
+fn zip_values<F: FnMut(f32, f32)>(a: VectorView, b: VectorView, mut f: F) {
+    match (a, b) {
+        (VectorView::Dense(x), VectorView::Dense(y)) => {
+            x.iter().zip(y).for_each(|(x, y)| f(*x, *y))
+        }
+        (VectorView::Dense(x), VectorView::Quantized(y, scale)) => {
+            x.iter().zip(y).for_each(|(x, y)| f(*x, (*y as f32) / scale))
+        }
+        (VectorView::Quantized(x, scale), VectorView::Dense(y)) => {
+            x.iter().zip(y).for_each(|(x, y)| f((*x as f32) / scale, *y))
+        }
+        (VectorView::Quantized(x, scale_x), VectorView::Quantized(y, scale_y)) => {
+            x.iter().zip(y).for_each(|(x, y)| f((*x as f32) / scale_x, (*y as f32) / scale_y))
+        }
+    }
+}
+
+// Posición donde empieza la cola.
+#[inline]
+fn tail_start(view: VectorView) -> usize {
+    view.len() - view.len() % SIMD_LANES
+}
+
+// Suma de `lanes` sobre los bloques y de `scalar` sobre la cola.
+#[inline]
+fn chunked_sum<L, S>(a: VectorView, b: VectorView, lanes: L, scalar: S) -> f32
+where
+    L: Fn(Lanes, Lanes) -> Lanes,
+    S: Fn(f32, f32) -> f32,
+{
+    let mut acc = Lanes::splat(0.0);
+    zip_blocks(a, b, |x, y| acc += lanes(x, y));
+    acc.reduce_sum() + (tail_start(a)..a.len()).map(|i| scalar(a.get(i), b.get(i))).sum::<f32>()
+}
+
+// Máximo de las diferencias absolutas, por bloques.
+#[inline]
+fn chunked_max_abs_diff(a: VectorView, b: VectorView) -> f32 {
+    let mut acc = Lanes::splat(0.0);
+    zip_blocks(a, b, |x, y| acc = acc.simd_max((x - y).abs()));
+    (tail_start(a)..a.len()).map(|i| (a.get(i) - b.get(i)).abs()).fold(acc.reduce_max(), f32::max)
+}
+
+fn simd_euclidean(a: VectorView, b: VectorView) -> f32 {
+    chunked_sum(a, b, |x, y| (x - y) * (x - y), |x, y| (x - y) * (x - y)).sqrt()
+}
+
+// Producto escalar y las dos normas en una sola pasada.
+fn simd_cosine(a: VectorView, b: VectorView) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (Lanes::splat(0.0), Lanes::splat(0.0), Lanes::splat(0.0));
+    zip_blocks(a, b, |x, y| {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    });
+    let (mut dot, mut norm_a, mut norm_b) = (dot.reduce_sum(), norm_a.reduce_sum(), norm_b.reduce_sum());
+    for i in tail_start(a)..a.len() {
+        let (x, y) = (a.get(i), b.get(i));
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    1.0 - dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+fn simd_dot(a: VectorView, b: VectorView) -> f32 {
+    -chunked_sum(a, b, |x, y| x * y, |x, y| x * y)
+}
+
+fn simd_manhattan(a: VectorView, b: VectorView) -> f32 {
+    chunked_sum(a, b, |x, y| (x - y).abs(), |x, y| (x - y).abs())
+}
 
-        result.unwrap_or_else(|| panic!("No se puede usar SIMD con longitud {len}"))
-    }};
+fn simd_chebyshev(a: VectorView, b: VectorView) -> f32 {
+    chunked_max_abs_diff(a, b)
 }
 
+// Los bits (valor > 0.5) distintos de cada bloque se cuentan en su máscara.
+fn simd_hamming(a: VectorView, b: VectorView) -> f32 {
+    let half = Lanes::splat(0.5);
+    let mut count = 0;
+    zip_blocks(a, b, |x, y| count += (x.simd_gt(half) ^ y.simd_gt(half)).to_bitmask().count_ones());
+    let tail = (tail_start(a)..a.len()).filter(|&i| (a.get(i) > 0.5) != (b.get(i) > 0.5)).count();
+    (count as usize + tail) as f32
+}
+
+fn simd_jaccard(a: VectorView, b: VectorView) -> f32 {
+    let min = chunked_sum(a, b, |x, y| x.simd_min(y), f32::min);
+    let max = chunked_sum(a, b, |x, y| x.simd_max(y), f32::max);
+    jaccard_from_sums(min, max)
+}
 
+// Distancia si ni la búsqueda ni la colección indican otra. Los kernels SIMD sirven para cualquier dimensión.
+pub const DEFAULT_DISTANCE_METHOD: DistanceMethod = DistanceMethod::SimdEuclidean;
+pub const DEFAULT_EF_SEARCH: usize = 64; // Candidatos que se exploran en la búsqueda aproximada si la petición no lo indica.
+pub const MAX_EF_SEARCH: usize = 10_000; // Máximo de candidatos de la búsqueda aproximada, aunque falten resultados.
+pub const MAX_RERANK: usize = 10_000; // Máximo de candidatos IVF-PQ que se recalculan con la distancia exacta.
 
 /// Define el tipo de búsqueda a realizar.
 pub enum SearchType {
     Exact,
-    Approximate,
+    Approximate, // Grafo HNSW.
+    Ivf,         // Listas invertidas (IVF-Flat).
+    IvfPq,       // Listas invertidas con códigos PQ (IVF-PQ).
+    Lsh,         // Hiperplanos aleatorios (LSH). Solo para la distancia coseno.
+    Vamana,      // Grafo Vamana en disco (DiskANN).
 }
 
 // Métodos de cálculo de distancia.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DistanceMethod {
     Euclidean,
     Cosine,
     SimdEuclidean,
     SimdCosine,
+    DotProduct,    // Producto escalar (máximo producto interno). La distancia es el producto cambiado de signo.
+    SimdDotProduct,
+    Manhattan,     // L1: suma de las diferencias absolutas.
+    SimdManhattan,
+    Chebyshev,     // L∞: mayor diferencia absoluta.
+    SimdChebyshev,
+    Minkowski(f32), // Lp con el exponente indicado (p >= 1). Sin versión SIMD: `std::simd` no tiene potencias.
+    Hamming,       // Bits distintos entre vectores binarios (0 o 1).
+    SimdHamming,
+    Jaccard,       // Jaccard ponderada: 1 - Σmin / Σmax, para valores no negativos (histogramas).
+    SimdJaccard,
 }
 
+impl DistanceMethod {
+    /// Comprueba que los valores de un vector tienen sentido para la métrica:
+    /// Hamming solo admite vectores binarios y Jaccard, valores no negativos.
+    pub fn validate_values(&self, values: &[f32]) -> Result<(), String> {
+        match self {
+            DistanceMethod::Hamming | DistanceMethod::SimdHamming if values.iter().any(|&x| x != 0.0 && x != 1.0) => {
+                Err("Hamming distance requires binary vectors (values 0 or 1)".to_string())
+            }
+            DistanceMethod::Jaccard | DistanceMethod::SimdJaccard if values.iter().any(|&x| x < 0.0) => {
+                Err("Jaccard distance requires non-negative values".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn validate_minkowski_p(p: f32) -> Result<(), String> {
+    if !p.is_finite() || p < 1.0 {
+        return Err("p must be a finite number of at least 1".to_string());
+    }
+    Ok(())
+}
+
+// Firma de las funciones de distancia. Guardan su método (la de Minkowski, con su exponente), así que no basta un puntero a función.
+pub type DistanceFn = Box<dyn Fn(&VFSVector, &VFSVector) -> f32 + Send + Sync>;
+
 
 /// Estructura que representa una métrica con su tipo de búsqueda asociado.
 pub struct Ranker {
     search_type: SearchType,
     distance_method: DistanceMethod,
-    ef_search: usize
+    ef_search: usize,
+    nprobe: Option<usize>, // Listas IVF a recorrer. Sin valor se usa el de la colección.
+    rerank: usize, // Candidatos IVF-PQ cuya distancia exacta se recalcula (0: ninguno).
+    probes: Option<usize>, // Cubos vecinos que visita la búsqueda LSH en cada tabla. Sin valor se usa el de la colección.
  //   manager: &'a mut VFSManager
 }
 
 impl Ranker {
     /// Constructor para crear una nueva instancia de `Ranker` con el tipo de búsqueda especificado.
-    pub fn new(search_type: SearchType, distance_method: DistanceMethod, ef_search: Option<usize>) -> Self {
-        Ranker{ search_type,  distance_method, ef_search: ef_search.unwrap_or(6)}
+    pub fn new(search_type: SearchType, distance_method: DistanceMethod, ef_search: Option<usize>, nprobe: Option<usize>, rerank: Option<usize>, probes: Option<usize>) -> Self {
+        Ranker{ search_type,  distance_method, ef_search: ef_search.unwrap_or(DEFAULT_EF_SEARCH), nprobe, rerank: rerank.unwrap_or(0), probes}
     }
 
     /// Método para realizar la búsqueda basada en el tipo especificado.
@@ -101,6 +251,14 @@ impl Ranker {
                 num_vectors_per_iteration,    
                 result_limit,
                 manager),
+
+            SearchType::Ivf => self.ivf_search(query, result_limit, manager),
+
+            SearchType::IvfPq => self.ivf_pq_search(query, result_limit, manager),
+
+            SearchType::Lsh => self.lsh_search(query, result_limit, manager),
+
+            SearchType::Vamana => self.vamana_search(query, result_limit, manager),
         }
     }
 
@@ -113,7 +271,7 @@ impl Ranker {
     /// - `query`: Referencia al vector de consulta.
     /// - `num_vectors_per_iteration`: Número de vectores a cargar por iteración.
     /// - `result_limit`: Número de vectores rankeados a devolver.
-
+    ///
     /// # Devuelve
     /// Un vector de tuplas que contiene el vector y su distancia con respecto al vector de consulta.
     fn exact_search(&mut self,
@@ -148,7 +306,8 @@ impl Ranker {
 
             let off = manager.get_current_offset();
             // Cargar un lote de vectores desde el archivo
-            let vectors = manager.load_batch(num_vectors_per_iteration).expect("Error al cargar el batch de vectores");
+            let vectors = manager.load_batch(num_vectors_per_iteration)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
             // Si no se cargaron vectores, hemos llegado al final del archivo
             if vectors.is_empty() {
                 println!("Final del archivo alcanzado. No hay más vectores a buscar");
@@ -186,126 +345,248 @@ impl Ranker {
     }
 
     /// Implementación de la búsqueda aproximada.
+    /// El manager mantiene un grafo HNSW con la métrica por defecto de la colección, así que normalmente basta con recorrerlo.
+    /// Si la búsqueda pide otra métrica, el grafo no sirve y se construye uno temporal a partir del archivo de datos.
     fn approximate_search(&mut self, query: &VFSVector,
         num_vectors_per_iteration: usize,     
         result_limit: Option<usize>,
         manager: &mut VFSManager
        
     ) -> io::Result<Vec<(u64, f32)>> {
-        // Implementación de la lógica para búsqueda aproximada.
         let limit = result_limit.unwrap_or(5);
 
-        // Crear la función de distancia según el método de distancia configurado
-        let distance_fn: Box<dyn Fn(&VFSVector, &VFSVector) -> f32> = Box::new(|vfs1, vfs2| {
-            self.calculate_distance(vfs1, vfs2)
-        });
-    
+        let mut all_results = match manager.ann_query(query, &self.distance_method, limit, self.ef_search)? {
+            Some(results) => results,
+            None => {
+                println!("El índice HNSW usa otra métrica, construyendo un índice temporal");
+                self.temporary_index_search(query, num_vectors_per_iteration, limit, manager)?
+            }
+        };
 
-        // Paso 1: Cargar todos los vectores en memoria  por lotes para construir el índice
-        
-        let ef_construction = self.ef_search;
-       
+        // Ordenar todos los resultados por distancia en orden ascendente
+        all_results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        // Limitar a la cantidad de resultados solicitados
+        if all_results.len() > limit {
+            all_results.truncate(limit);
+        }
+
+        println!("Búsqueda aproximada completada: encontrados {} resultados", all_results.len());
+
+        Ok(all_results)
+    }
+
+    /// Búsqueda en el índice IVF del manager: solo se recorren las `nprobe` listas más cercanas a la consulta.
+    /// La primera búsqueda (o la primera con otra métrica) entrena el índice con todos los vectores.
+    fn ivf_search(&mut self, query: &VFSVector,
+        result_limit: Option<usize>,
+        manager: &mut VFSManager
+    ) -> io::Result<Vec<(u64, f32)>> {
+        let limit = result_limit.unwrap_or(5);
+        let results = manager.ivf_query(query, &self.distance_method, limit, self.nprobe)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        println!("Búsqueda IVF completada: encontrados {} resultados", results.len());
+        Ok(results)
+    }
+
+    /// Búsqueda en el índice IVF-PQ del manager. Las distancias se calculan con los códigos PQ,
+    /// salvo que se pida recalcular las de los `rerank` mejores candidatos con los vectores originales.
+    fn ivf_pq_search(&mut self, query: &VFSVector,
+        result_limit: Option<usize>,
+        manager: &mut VFSManager
+    ) -> io::Result<Vec<(u64, f32)>> {
+        if !supports_distance(&self.distance_method) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "IVF-PQ search does not support Chebyshev or Jaccard distance"));
+        }
+        let limit = result_limit.unwrap_or(5);
+        let results = manager.ivf_pq_query(query, &self.distance_method, limit, self.nprobe, self.rerank)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        println!("Búsqueda IVF-PQ completada: encontrados {} resultados", results.len());
+        Ok(results)
+    }
+
+    /// Búsqueda LSH: el índice del manager devuelve los vectores que comparten cubo con la consulta
+    /// y aquí se calcula su distancia exacta para quedarnos con los mejores.
+    fn lsh_search(&mut self, query: &VFSVector,
+        result_limit: Option<usize>,
+        manager: &mut VFSManager
+    ) -> io::Result<Vec<(u64, f32)>> {
+        if !matches!(self.distance_method, DistanceMethod::Cosine | DistanceMethod::SimdCosine) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "LSH search only supports cosine distance"));
+        }
+        let limit = result_limit.unwrap_or(5);
+        let candidates = manager.lsh_candidates(query, self.probes)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        let mut results: Vec<(u64, f32)> = candidates.into_iter()
+            .filter_map(|id| manager.get_vector_by_id(id))
+            .map(|vector| (vector.id(), self.calculate_distance(query, &vector)))
+            .collect();
+        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(limit);
+        println!("Búsqueda LSH completada: encontrados {} resultados", results.len());
+        Ok(results)
+    }
 
-        // Variable para almacenar todos los resultados de las búsquedas en diferentes lotes
-        let mut all_results: Vec<(u64, f32)> = Vec::new();
+    /// Búsqueda en el grafo Vamana del manager. `ef_search` es el tamaño de la lista de candidatos;
+    /// los vectores completos de los nodos visitados se leen del disco para calcular la distancia exacta.
+    fn vamana_search(&mut self, query: &VFSVector,
+        result_limit: Option<usize>,
+        manager: &mut VFSManager
+    ) -> io::Result<Vec<(u64, f32)>> {
+        let limit = result_limit.unwrap_or(5);
+        let results = manager.vamana_query(query, &self.distance_method, limit, self.ef_search.max(limit))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        println!("Búsqueda Vamana completada: encontrados {} resultados", results.len());
+        Ok(results)
+    }
 
+    /// Construye un índice HNSW con todos los vectores del archivo de datos y lo consulta.
+    fn temporary_index_search(&self, query: &VFSVector,
+        num_vectors_per_iteration: usize,
+        limit: usize,
+        manager: &mut VFSManager
+    ) -> io::Result<Vec<(u64, f32)>> {
+        // Paso 1: Construir el índice HNSW con la métrica de la búsqueda y los parámetros de la colección
+        let params = *manager.index_params();
+        let mut ann_index = VFSANNIndex::<DistanceFn, SmallRng>::new(distance_fn(&self.distance_method), params);
 
-        // Paso 2: Construir el índice HNSW
-        let mut ann_index = VFSANNIndex::<_, SmallRng>::new(distance_fn, Some(ef_construction));
-        // Oh no, el vector no estaba en la memtable.
+        // Paso 2: Cargar todos los vectores en memoria por lotes para llenar el índice
         let options = ResetOptions::default(); // resetea el offset poniendolo a 0.
-        
 
         if let Err(e) = manager.reset_state(options) {
             println!("Error al resetear el estado del manager");
             return Err(e);
         }
-        
 
         loop {
             let vectors = manager.load_batch(num_vectors_per_iteration)
-            .expect("Error al cargar el batch de vectores");
-        
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
             if vectors.is_empty() {
                 println!("Final del archivo alcanzado. No hay más vectores a leer");
-             break;
+                break;
             }
-        
+
             ann_index.insert_many(vectors);
-            
-             
+        }
 
-        
+        // Paso 3: Realizar la búsqueda aproximada y quedarnos con los ids
+        let ann_results = ann_index.query(query, limit, self.ef_search)?;
+        Ok(ann_results.iter().map(|(vector, distance)| (vector.id(), *distance)).collect())
     }
 
-    // Paso 3: Realizar la búsqueda aproximada
-    let ann_results = ann_index.query(query)?; // Usar ? para manejar errores
+    /// Método para calcular la distancia entre dos vectores.
+    fn calculate_distance(&self, vector1: &VFSVector, vector2: &VFSVector) -> f32 {
+        view_distance(&self.distance_method, vector1.view(), vector2.view())
+    }
+}
 
-    // Paso 4: Convertir los resultados al formato esperado
-    for (vector, distance) in &ann_results {
-        let id = vector.id();
-        all_results.push((id, *distance));
+/// Función de distancia asociada a cada método.
+/// No dependen de un `Ranker`, así el índice HNSW del manager puede guardarlas.
+pub fn distance_fn(distance_method: &DistanceMethod) -> DistanceFn {
+    let distance_method = *distance_method;
+    Box::new(move |vector1: &VFSVector, vector2: &VFSVector| {
+        view_distance(&distance_method, vector1.view(), vector2.view())
+    })
+}
+
+/// Distancia entre dos vistas con la métrica indicada. No reserva memoria: los valores se leen prestados
+/// y los cuantizados se descuantizan sobre la marcha. La usan también los índices IVF y Vamana con sus centroides.
+pub fn view_distance(distance_method: &DistanceMethod, a: VectorView, b: VectorView) -> f32 {
+    check_dimensions(a, b);
+    match *distance_method {
+        DistanceMethod::Euclidean => euclidean(a, b),
+        DistanceMethod::Cosine => cosine(a, b),
+        DistanceMethod::SimdEuclidean => simd_euclidean(a, b),
+        DistanceMethod::SimdCosine => simd_cosine(a, b),
+        DistanceMethod::DotProduct => dot_product(a, b),
+        DistanceMethod::SimdDotProduct => simd_dot(a, b),
+        DistanceMethod::Manhattan => manhattan(a, b),
+        DistanceMethod::SimdManhattan => simd_manhattan(a, b),
+        DistanceMethod::Chebyshev => chebyshev(a, b),
+        DistanceMethod::SimdChebyshev => simd_chebyshev(a, b),
+        DistanceMethod::Minkowski(p) => minkowski(a, b, p),
+        DistanceMethod::Hamming => hamming(a, b),
+        DistanceMethod::SimdHamming => simd_hamming(a, b),
+        DistanceMethod::Jaccard => jaccard(a, b),
+        DistanceMethod::SimdJaccard => simd_jaccard(a, b),
     }
+}
 
-    if ann_results.len() == 0 as usize {
-        return Ok(Vec::new());
+// Verificar que los vectores tengan la misma dimensión
+fn check_dimensions(a: VectorView, b: VectorView) {
+    if a.len() != b.len() {
+        panic!("Los vectores deben tener la misma dimensión");
     }
+}
 
-    
+// Simple cálculo de distancia euclidiana.
+// Calcular la suma de los cuadrados de las diferencias
+fn euclidean(a: VectorView, b: VectorView) -> f32 {
+    let mut sum = 0.0;
+    zip_values(a, b, |x, y| sum += (x - y).powi(2));
+    sum.sqrt()
+}
 
-    // Ordenar todos los resultados por distancia en orden ascendente
-    all_results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+// Calcular el coseno
+fn cosine(a: VectorView, b: VectorView) -> f32 {
+    let (mut dot, mut norm_1, mut norm_2) = (0.0, 0.0, 0.0);
+    zip_values(a, b, |x, y| {
+        dot += x * y;
+        norm_1 += x.powi(2);
+        norm_2 += y.powi(2);
+    });
 
-    // Limitar a la cantidad de resultados solicitados
-    if all_results.len() > limit {
-        all_results.truncate(limit);
-    }
+    1.0 - (dot / (norm_1.sqrt() * norm_2.sqrt()))
+}
 
-    println!("Búsqueda aproximada completada: encontrados {} resultados", all_results.len());
+// Producto escalar con el signo cambiado: cuanto mayor es el producto, menor es la distancia,
+// así se ordena igual que las demás distancias (de menor a mayor). Puede ser negativa.
+fn dot_product(a: VectorView, b: VectorView) -> f32 {
+    let mut dot = 0.0;
+    zip_values(a, b, |x, y| dot += x * y);
+    -dot
+}
 
-    Ok(all_results)
-        
-    }
+// Las tablas PQ usan también Manhattan y Hamming sobre subvectores.
 
-    /// Método para calcular la distancia entre dos vectores.
-    fn calculate_distance(&self, vector1: &VFSVector, vector2: &VFSVector) -> f32 {
-        /// Verificar que los vectores tengan la misma dimensión
-        if vector1.as_f32_vec().len() != vector2.as_f32_vec().len() {
-            panic!("Los vectores deben tener la misma dimensión");
-        }
+pub(super) fn manhattan(a: VectorView, b: VectorView) -> f32 {
+    let mut sum = 0.0;
+    zip_values(a, b, |x, y| sum += (x - y).abs());
+    sum
+}
 
-        
-        match self.distance_method {
-            // Simple cálculo de distancia euclidiana.
-            // Calcular la suma de los cuadrados de las diferencias
-            DistanceMethod::Euclidean => {
-                vector1.as_f32_vec().iter()
-                    .zip(vector2.as_f32_vec().iter())
-                    .map(|(a, b)| (a - b).powi(2))
-                    .sum::<f32>()
-                    .sqrt()
-            }
-        
-            // Calcular el coseno
-            DistanceMethod::Cosine => {
-                let dot: f32 = vector1.as_f32_vec().iter()
-                    .zip(vector2.as_f32_vec().iter())
-                    .map(|(a, b)| a * b)
-                    .sum();
+fn chebyshev(a: VectorView, b: VectorView) -> f32 {
+    let mut max = 0.0;
+    zip_values(a, b, |x, y| max = f32::max(max, (x - y).abs()));
+    max
+}
 
-                let norm_1: f32 = vector1.as_f32_vec().iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
-                let norm_2: f32 = vector2.as_f32_vec().iter().map(|x| x.powi(2)).sum::<f32>().sqrt();
+fn minkowski(a: VectorView, b: VectorView, p: f32) -> f32 {
+    let mut sum = 0.0;
+    zip_values(a, b, |x, y| sum += (x - y).abs().powf(p));
+    sum.powf(1.0 / p)
+}
 
-                1.0 - (dot / (norm_1 * norm_2))
-            }
+// Un valor cuenta como bit a 1 si pasa de 0.5. Con vectores binarios es lo mismo que comparar los valores,
+// y con los centroides de IVF (medias de bits) equivale a quedarse con el bit mayoritario.
+pub(super) fn hamming(a: VectorView, b: VectorView) -> f32 {
+    let mut count = 0;
+    zip_values(a, b, |x, y| count += ((x > 0.5) != (y > 0.5)) as usize);
+    count as f32
+}
 
-            DistanceMethod::SimdEuclidean => {
-                dynamic_simd_euclidean!(vector1, vector2, [2, 4, 8, 16, 32, 64])
-            }
-        
-            DistanceMethod::SimdCosine => {
-                dynamic_simd_cosine!(vector1, vector2, [2, 4, 8, 16, 32, 64])
-            }
-    }
-    
-}}
\ No newline at end of file
+fn jaccard(a: VectorView, b: VectorView) -> f32 {
+    let (mut min, mut max) = (0.0, 0.0);
+    zip_values(a, b, |x, y| {
+        min += x.min(y);
+        max += x.max(y);
+    });
+    jaccard_from_sums(min, max)
+}
+
+// Dos vectores nulos son iguales.
+fn jaccard_from_sums(min: f32, max: f32) -> f32 {
+    if max > 0.0 { 1.0 - min / max } else { 0.0 }
+}
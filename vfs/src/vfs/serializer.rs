@@ -1,17 +1,106 @@
 use std::fs::{OpenOptions, File};
-use std::io::{self,Write, Read, Seek, SeekFrom};
+use std::io::{self,Write, Read, Seek, SeekFrom, BufReader};
 use std::option::Option;
 use bincode;
+use crc32fast::Hasher;
+use memmap2::Mmap;
 use super::vector::{Vector, VFSVector, QuantizedVector};
+use super::err::VFSError;
 
+// Formato del archivo de datos (versión 2):
+//
+// CABECERA DEL ARCHIVO (8 bytes)
+// | magic "VFSD" (4) | versión del formato: u32 LE (4) |
+//
+// REGISTROS (uno detrás de otro)
+// | marca (4) | tamaño de los datos: u32 LE (4) | CRC32 del tamaño y los datos: u32 LE (4) | datos: bincode |
+//
+// El tamaño tiene un ancho fijo para que los archivos sean portables entre builds de 32 y 64 bits.
+// La versión 1 era el formato original, sin cabecera ni checksums y con el tamaño guardado como usize.
 
-const fn usize_size() -> usize {
-    std::mem::size_of::<usize>()
-}
+const FILE_MAGIC: [u8; 4] = *b"VFSD";
+const FORMAT_VERSION: u32 = 2;
+pub const FILE_HEADER_LEN: usize = FILE_MAGIC.len() + 4;
 
-const INT_SIZE: usize = usize_size();
 const START_MARKER: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+const TOMBSTONE_MARKER: [u8; 4] = [0xDE, 0xAD, 0xC0, 0xDE]; // Marca las lápidas (vectores borrados).
+const MARKER_LEN: usize = START_MARKER.len();
+const LEN_SIZE: usize = std::mem::size_of::<u32>();
+const CRC_SIZE: usize = std::mem::size_of::<u32>();
+const RECORD_HEADER_LEN: usize = MARKER_LEN + LEN_SIZE + CRC_SIZE;
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024; // Buffer de lectura por defecto (8KB). No limita el tamaño de los registros.
+
+
+// Escribe en un archivo temporal y lo renombra, para no dejar el archivo a medias si el proceso se cae.
+pub fn write_file_atomically(path: &str, bytes: &[u8]) -> Result<(), VFSError> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
 
+// Crea (o trunca) un archivo de datos vacío, escribiendo solo la cabecera.
+pub fn create_data_file(path: &str) -> io::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(&file_header())?;
+    Ok(())
+}
+
+fn file_header() -> [u8; FILE_HEADER_LEN] {
+    let mut header = [0u8; FILE_HEADER_LEN];
+    header[..FILE_MAGIC.len()].copy_from_slice(&FILE_MAGIC);
+    header[FILE_MAGIC.len()..].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header
+}
+
+// Abre un archivo de datos y comprueba su cabecera.
+// Devuelve el archivo y su tamaño. Un archivo vacío es válido (la cabecera se escribe con el primer registro).
+pub fn open_data_file(path: &str) -> Result<(File, usize), VFSError> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len() as usize;
+    if file_len == 0 {
+        return Ok((file, 0));
+    }
+    if file_len < FILE_HEADER_LEN {
+        return Err(VFSError::CorruptedData(0, "Incomplete file header".to_string()));
+    }
+
+    let mut header = [0u8; FILE_HEADER_LEN];
+    file.read_exact(&mut header)?;
+    if header[..FILE_MAGIC.len()] != FILE_MAGIC {
+        return Err(VFSError::UnsupportedFormat(format!("{} is not a VFS data file", path)));
+    }
+    let version = u32::from_le_bytes(header[FILE_MAGIC.len()..].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(VFSError::UnsupportedFormat(format!("{} uses format version {}, expected {}", path, version, FORMAT_VERSION)));
+    }
+
+    Ok((file, file_len))
+}
+
+fn record_checksum(len_bytes: &[u8], bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(len_bytes);
+    hasher.update(bytes);
+    hasher.finalize()
+}
 
 
 // Función para serializar un vector y guardarlo en el archivo que viene dado por path.
@@ -23,11 +112,6 @@ const START_MARKER: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
 // Devuelve:
 // - usize: el offset donde se guardó el vector.
 pub fn save_vector(entry: &VFSVector, path: &str) -> std::io::Result<(usize)> {
-    // Crear el directorio si no existe
-    if let Some(parent) = std::path::Path::new(path).parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-
     let bytes = match bincode::serialize(entry){
         Ok(b) => b,
         Err(e) => {
@@ -36,118 +120,306 @@ pub fn save_vector(entry: &VFSVector, path: &str) -> std::io::Result<(usize)> {
         }
     };
 
+    append_record(&START_MARKER, &bytes, path)
+}
+
+// Guarda una lápida para el vector con identificador `id`.
+// El archivo de datos es append-only, así que borrar un vector consiste en escribir un registro
+// con la marca `TOMBSTONE_MARKER` seguida del id borrado. Al leer, estos registros se saltan.
+// Devuelve el offset donde se guardó la lápida.
+pub fn save_tombstone(id: u64, path: &str) -> std::io::Result<usize> {
+    let bytes = match bincode::serialize(&id) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("Error serializando la lápida: {}", e);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Error serializando la lápida"));
+        }
+    };
+
+    append_record(&TOMBSTONE_MARKER, &bytes, path)
+}
+
+// Escribe un registro al final del archivo: marca, tamaño, checksum y datos.
+// Si el archivo está vacío, escribe antes la cabecera.
+// Devuelve el offset donde empieza el registro.
+fn append_record(marker: &[u8; 4], bytes: &[u8], path: &str) -> io::Result<usize> {
+    // Crear el directorio si no existe
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
 
-    // Tamaño del vector (en bytes)
-    let size = bytes.len() as usize;
+    // Tamaño del registro (en bytes)
+    let size = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Registro demasiado grande"))?;
+    let len_bytes = size.to_le_bytes();
 
 
     let mut file = OpenOptions::new()
         .append(true)
         .read(true) // importante: necesitamos poder hacer seek
         .open(path)?;
-    
+
     // Obtener offset actual antes de escribir
-    let offset = file.seek(SeekFrom::End(0))?;
+    let mut offset = file.seek(SeekFrom::End(0))?;
+    if offset == 0 {
+        file.write_all(&file_header())?;
+        offset = FILE_HEADER_LEN as u64;
+    }
+
+    // El registro completo se escribe de una vez.
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + bytes.len());
+    record.extend_from_slice(marker);
+    record.extend_from_slice(&len_bytes);
+    record.extend_from_slice(&record_checksum(&len_bytes, bytes).to_le_bytes());
+    record.extend_from_slice(bytes);
 
-    // Escribir la marca de inicio
-    if let Err(e) = file.write_all(&START_MARKER) {
-        eprintln!("Error escribiendo la marca de inicio en el archivo: {}", e);
+    if let Err(e) = file.write_all(&record) {
+        eprintln!("Error escribiendo el registro en el archivo: {}", e);
         return Err(e);
     }
 
-    // Escribir el tamaño del vector
-    if let Err(e) = file.write_all(&size.to_le_bytes()) {
-        eprintln!("Error escribiendo el tamaño del vector en el archivo: {}", e);
-        return Err(e);
+    Ok(offset.try_into().unwrap())
+}
+
+// Registro del archivo de datos: un vector o una lápida.
+pub enum Record {
+    Vector(VFSVector),
+    Tombstone(u64),
+}
+
+// Valida la cabecera de un registro (`RECORD_HEADER_LEN` bytes) que empieza en `offset`.
+// Devuelve la marca y el tamaño de los datos.
+fn parse_record_header(header: &[u8], offset: usize) -> Result<([u8; 4], usize), VFSError> {
+    let marker: [u8; 4] = header[..MARKER_LEN].try_into().unwrap();
+    if marker != START_MARKER && marker != TOMBSTONE_MARKER {
+        return Err(VFSError::CorruptedData(offset, "Record marker not found".to_string()));
     }
+    let size = u32::from_le_bytes(header[MARKER_LEN..MARKER_LEN + LEN_SIZE].try_into().unwrap()) as usize;
+    Ok((marker, size))
+}
 
-    // Escribir los datos del vector
-    if let Err(e) = file.write_all(&bytes) {
-        eprintln!("Error escribiendo los datos del vector en el archivo: {}", e);
-        return Err(e);
+// Comprueba el checksum de un registro completo (cabecera + datos) y deserializa sus datos.
+fn decode_record(record: &[u8], marker: [u8; 4], offset: usize) -> Result<Record, VFSError> {
+    let len_bytes = &record[MARKER_LEN..MARKER_LEN + LEN_SIZE];
+    let stored_crc = u32::from_le_bytes(record[MARKER_LEN + LEN_SIZE..RECORD_HEADER_LEN].try_into().unwrap());
+    let bytes = &record[RECORD_HEADER_LEN..];
+    if record_checksum(len_bytes, bytes) != stored_crc {
+        return Err(VFSError::CorruptedData(offset, "Checksum mismatch".to_string()));
     }
 
-    Ok(offset.try_into().unwrap())
+    let decoded = if marker == START_MARKER {
+        bincode::deserialize::<VFSVector>(bytes).map(Record::Vector)
+    } else {
+        bincode::deserialize::<u64>(bytes).map(Record::Tombstone)
+    };
+    decoded.map_err(|e| VFSError::CorruptedData(offset, format!("Invalid record data: {}", e)))
+}
+
+// Lector secuencial de registros del archivo de datos.
+// Usa un `BufReader` que se va rellenando desde el archivo, así que los registros pueden ser más grandes que el buffer.
+// Como iterador devuelve `(offset del registro, registro)`. Tras un error deja de devolver registros
+// hasta que se llama a `seek` (la recuperación lo usa para buscar el siguiente registro válido).
+pub struct RecordReader {
+    reader: BufReader<File>,
+    offset: usize,    // Offset del siguiente registro a leer.
+    file_len: usize,  // Tamaño del archivo al abrirlo. Lo escrito después no se lee.
+    needs_seek: bool, // El cursor del archivo no está en `offset`.
+    failed: bool,
+}
+
+impl RecordReader {
+    // Abre `path` para leer los registros a partir de `offset`.
+    // `buffer_size` es el tamaño del buffer de lectura (por defecto `DEFAULT_BUFFER_SIZE`).
+    pub fn open(path: &str, offset: usize, buffer_size: Option<usize>) -> Result<Self, VFSError> {
+        let (file, file_len) = open_data_file(path)?;
+        let buffer_size = buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        Ok(Self {
+            reader: BufReader::with_capacity(buffer_size, file),
+            offset: offset.max(FILE_HEADER_LEN), // Los registros empiezan después de la cabecera del archivo.
+            file_len,
+            needs_seek: true,
+            failed: false,
+        })
+    }
+
+    // Offset del siguiente registro (o el final del archivo si ya se leyó todo).
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn file_len(&self) -> usize {
+        self.file_len
+    }
+
+    // Continúa la lectura en `offset`.
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset.max(FILE_HEADER_LEN);
+        self.needs_seek = true;
+        self.failed = false;
+    }
+
+    // Lee el registro que empieza en el offset actual.
+    // Devuelve:
+    // - Ok(Some((offset, registro))) si hay un registro válido.
+    // - Ok(None) al llegar al final del archivo.
+    // - Err(CorruptedData) si en el offset actual no empieza un registro completo y válido (marca desconocida,
+    //   registro cortado, checksum incorrecto o datos que no se pueden deserializar).
+    pub fn next_record(&mut self) -> Result<Option<(usize, Record)>, VFSError> {
+        let offset = self.offset;
+        if offset >= self.file_len {
+            return Ok(None);
+        }
+        if self.file_len - offset < RECORD_HEADER_LEN {
+            return Err(VFSError::CorruptedData(offset, "Incomplete record".to_string()));
+        }
+
+        if self.needs_seek {
+            self.reader.seek(SeekFrom::Start(offset as u64))?;
+            self.needs_seek = false;
+        }
+        // Si la lectura falla a mitad, el cursor queda en una posición desconocida.
+        self.needs_seek = true;
+
+        let mut record = vec![0u8; RECORD_HEADER_LEN];
+        self.reader.read_exact(&mut record)?;
+
+        let (marker, size) = parse_record_header(&record, offset)?;
+        if size > self.file_len - offset - RECORD_HEADER_LEN {
+            return Err(VFSError::CorruptedData(offset, "Incomplete record".to_string()));
+        }
+
+        record.resize(RECORD_HEADER_LEN + size, 0);
+        self.reader.read_exact(&mut record[RECORD_HEADER_LEN..])?;
+
+        let decoded = decode_record(&record, marker, offset)?;
+        self.offset = offset + record.len();
+        self.needs_seek = false;
+        Ok(Some((offset, decoded)))
+    }
+
+    // Iterador sobre los vectores, saltando las lápidas.
+    // Se toma por referencia para poder consultar `offset()` después de leer.
+    pub fn vectors(&mut self) -> impl Iterator<Item = Result<(usize, VFSVector), VFSError>> + '_ {
+        self.filter_map(|item| match item {
+            Ok((offset, Record::Vector(entry))) => Some(Ok((offset, entry))),
+            Ok((_, Record::Tombstone(_))) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = Result<(usize, Record), VFSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Archivo de datos proyectado en memoria (mmap).
+// Las lecturas se hacen directamente sobre la page cache, sin abrir el archivo ni copiar a un buffer intermedio.
+// La proyección tiene el tamaño del archivo al abrirlo: quien la usa debe volver a abrirla cuando el archivo crece
+// y descartarla antes de truncarlo o sustituirlo.
+pub struct MappedReader {
+    mmap: Mmap,
+}
+
+impl MappedReader {
+    pub fn open(path: &str) -> Result<Self, VFSError> {
+        let (file, _) = open_data_file(path)?;
+        // SAFETY: el archivo de datos solo se modifica añadiendo registros al final, y el manager descarta
+        // la proyección antes de truncarlo o reemplazarlo, así que los bytes proyectados no cambian.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    // Lee el registro que empieza en `offset`. Devuelve el registro y el offset del siguiente,
+    // o `None` si `offset` es el final de la proyección.
+    pub fn read_record(&self, offset: usize) -> Result<Option<(Record, usize)>, VFSError> {
+        let data = &self.mmap[..];
+        let offset = offset.max(FILE_HEADER_LEN);
+        if offset >= data.len() {
+            return Ok(None);
+        }
+        if data.len() - offset < RECORD_HEADER_LEN {
+            return Err(VFSError::CorruptedData(offset, "Incomplete record".to_string()));
+        }
+
+        let (marker, size) = parse_record_header(&data[offset..offset + RECORD_HEADER_LEN], offset)?;
+        if size > data.len() - offset - RECORD_HEADER_LEN {
+            return Err(VFSError::CorruptedData(offset, "Incomplete record".to_string()));
+        }
+
+        let end = offset + RECORD_HEADER_LEN + size;
+        let decoded = decode_record(&data[offset..end], marker, offset)?;
+        Ok(Some((decoded, end)))
+    }
+
+    // Iterador sobre los registros a partir de `offset`.
+    pub fn records(&self, offset: usize) -> MappedRecords<'_> {
+        MappedRecords { reader: self, offset: offset.max(FILE_HEADER_LEN), failed: false }
+    }
+}
+
+// Iterador secuencial sobre un `MappedReader`. Funciona igual que `RecordReader`.
+pub struct MappedRecords<'a> {
+    reader: &'a MappedReader,
+    offset: usize,
+    failed: bool,
+}
+
+impl MappedRecords<'_> {
+    // Offset del siguiente registro.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for MappedRecords<'_> {
+    type Item = Result<(usize, Record), VFSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.reader.read_record(self.offset) {
+            Ok(Some((record, next_offset))) => {
+                let offset = self.offset;
+                self.offset = next_offset;
+                Some(Ok((offset, record)))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
 }
 
 // Función para cargar un número determinado de vectores en memoria.
 // Sirve para cargar el buffer con los vectores que nos interesan.
 // count es el número de vectores a cargar.
 // offset es la posición en el archivo donde empezamos a leer.
-pub fn load_vectors(path: &str, offset: usize, count: usize, buffer_size: Option<usize>) -> io::Result<(Vec<VFSVector>, usize)> {
-    // Abrir el archivo en modo lectura
-    let mut file = File::open(path)?;
-    let mut entries = Vec::with_capacity(count);
-    let mut current_offset = offset;
-    let buffer_size = buffer_size.unwrap_or(1024); // Por defecto cargamos 1KB en memoria.
-
-    // Mover el cursor del archivo al offset especificado
-    file.seek(SeekFrom::Start(current_offset as u64))?;
-
-   
-    let marker_len = START_MARKER.len();
-    let mut buffer = vec![0; buffer_size]; // Tamaño del buffer (ajustable según sea necesario)
-
-
-    // Leer un bloque del archivo
-    let bytes_read = file.read(&mut buffer)?;
-    println!("Bytes a leer: {}", &bytes_read);
-
-    if bytes_read == 0 {
-        return Ok((Vec::new(), current_offset));// Fin del archivo
-    }
-
-    let mut cursor = 0; // Cursor para avanzar en el archivo.
-    while cursor + marker_len <= bytes_read &&  entries.len() <= count - 1 { 
-        // Para de leer cuando el buffer esta lleno
-        // Buscar la marca de inicio en el buffer
-        if &buffer[cursor..cursor + marker_len] == START_MARKER { 
-            // Si el primer elemento coincide con la marca de inicio del vector, leer.
-            // Saltar la marca de inicio
-            println!("Marca de inicio de vector encontrada!");
-            cursor += marker_len;
-
-            
-
-            // Leer el tamaño del vector
-            if cursor + INT_SIZE <= bytes_read {
-                    
-                let size_slice = &buffer[cursor..cursor + INT_SIZE];
-                let vector_size = usize::from_le_bytes(size_slice.try_into().unwrap());
-                cursor += INT_SIZE;
-                println!("Tamaño del vector: {}", vector_size);
-
-                // Verificar que hay suficientes bytes para leer el vector completo
-                if cursor + vector_size <= bytes_read {
-                    println!("Hay suficientes bytes para leer el vector en el buffer");
-                    let vector_slice = &buffer[cursor..cursor + vector_size];
-                
-                    match bincode::deserialize::<VFSVector>(vector_slice) {
-                        Ok(entry) => {
-                            entries.push(entry);
-                           
-                        },
-                        Err(e) => {
-                            eprintln!("Error de deserialización: {:?}", e);
-                            cursor = 0;
-                            break;
-                        },
-                    }
-                    cursor += vector_size;
-                } else {
-                    eprintln!("No hay suficientes bytes para leer el vector");
-                    cursor = 0;
-                    break;
-                }
-            } else {
-                eprintln!("No hay suficientes bytes para leer el tamaño del vector");
-                cursor = 0;
-                break;
-            }
-        } else {
-                cursor += 1; // Avanzar al siguiente byte
-        }
-    }
-    current_offset += cursor;
-    Ok((entries, current_offset))
-}
\ No newline at end of file
+// Cada vector se devuelve junto al offset de su registro, para poder distinguir versiones antiguas de un mismo id.
+// También se devuelve el offset del siguiente registro, para continuar la lectura.
+// Si un registro está dañado se devuelve `VFSError::CorruptedData` con su offset.
+pub fn load_vectors(path: &str, offset: usize, count: usize, buffer_size: Option<usize>) -> Result<(Vec<(usize, VFSVector)>, usize), VFSError> {
+    let mut reader = RecordReader::open(path, offset, buffer_size)?;
+    let entries = reader.vectors().take(count).collect::<Result<Vec<_>, _>>()?;
+    Ok((entries, reader.offset()))
+}
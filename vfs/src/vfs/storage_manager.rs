@@ -1,28 +1,201 @@
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader,  Read, Write};
+use std::io::{self, Read, Write};
 use bincode;
 use super::vector::{VFSVector}; // Asegúrate de importar correctamente
-use super::serializer::{save_vector, load_vectors}; // Funciones de acceso a disco
+use super::serializer::{save_vector, save_tombstone, create_data_file, open_data_file, write_file_atomically, RecordReader, MappedReader, Record, FILE_HEADER_LEN}; // Funciones de acceso a disco
 use super::err::VFSError;
+use super::wal::{Wal, WalEntry};
+use super::cache::{VectorCache, CacheStats, DEFAULT_CACHE_BYTES};
+use super::collection::CollectionPaths;
+use super::ann::{VFSANNIndex, IndexSnapshot, IndexStats, HnswParams, LshIndex, LshParams, LshStats, default_build_threads};
+use super::ivf::{IvfIndex, IvfParams, IvfStats};
+use super::pq::{IvfPqIndex, IvfPqStats};
+use super::vamana::{VamanaIndex, VamanaParams, VamanaStats};
+use super::rank::{DistanceMethod, DistanceFn, DEFAULT_DISTANCE_METHOD, distance_fn};
+use rand::rngs::SmallRng;
 use std::simd::{SupportedLaneCount, LaneCount};
 use core::simd::Simd;
 use serde::{Serialize, Deserialize};
-use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet};
 
 const FLUSH_THRESHOLD: usize = 10; // Número de vectores que se pueden almacenar en memoria antes de flushear la memtable.
-const STORAGE_PATH: &str = "data/vectors.dat";
-const VFS_STATE_PATH: &str =  "state/vfs_state.bin";
+const COMPACTION_BUFFER_SIZE: usize = 64 * 1024; // Buffer de lectura al compactar (64KB).
+const INDEX_BATCH_SIZE: usize = 4096; // Vectores que se leen del archivo de datos antes de añadirlos al grafo en paralelo.
+const IVF_RETRAIN_GROWTH: usize = 2; // Los índices IVF se vuelven a entrenar cuando la colección multiplica su tamaño por este factor.
 
 use indexmap::IndexMap; 
 
+// Índice HNSW que mantiene el manager.
+pub type ManagedIndex = VFSANNIndex<DistanceFn, SmallRng>;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct VFSState{
     name: String,
     next_id: u64,
     current_offset: usize,
     index_map: BTreeMap<u64, usize>,
-    quantize: bool
+    quantize: bool,
+    deleted: BTreeSet<u64>,
+    data_len: u64, // Tamaño del archivo de datos al guardar el estado. Si no coincide, el estado está desactualizado.
+
+}
+
+
+// Grafo HNSW guardado en disco junto al snapshot del estado.
+#[derive(Serialize, Deserialize)]
+struct HnswState {
+    distance_method: DistanceMethod,
+    data_len: u64, // Parte del archivo de datos que ya está en el grafo. Los registros posteriores se añaden al cargarlo.
+    graph: IndexSnapshot,
+}
+
+
+// Índices que se construyen con los vectores vigentes la primera vez que se busca con ellos (IVF, IVF-PQ, LSH y Vamana).
+// Se guardan junto al archivo de datos y al cargarlos se les añaden los registros escritos después de guardarlos.
+trait TrainedIndex: Serialize + DeserializeOwned {
+    type Params;
+    const NAME: &'static str;
+    fn saved_path(paths: &CollectionPaths) -> String; // Archivo con el índice y la longitud del archivo de datos que cubre.
+    fn remove_saved(paths: &CollectionPaths) -> io::Result<()>;
+    fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &Self::Params, paths: &CollectionPaths) -> Result<Self, VFSError>;
+    // Si hay que volver a construirlo para buscar con esta métrica y estos parámetros.
+    fn needs_training(&self, distance_method: DistanceMethod, params: &Self::Params) -> bool;
+    fn insert_vector(&mut self, vector: &VFSVector) -> io::Result<()>;
+    fn remove_vector(&mut self, id: u64);
+    fn len(&self) -> usize;
+    // Si un índice cargado del disco se puede usar. Solo falla si depende de otro archivo que ya no existe.
+    fn is_complete(&self) -> bool {
+        true
+    }
+}
+
+// Las listas del IVF-Flat están en `paths.ivf`, junto a los centroides.
+impl TrainedIndex for IvfIndex {
+    type Params = IvfParams;
+    const NAME: &'static str = "IVF";
+    fn saved_path(paths: &CollectionPaths) -> String {
+        IvfIndex::index_path(&paths.ivf)
+    }
+    fn remove_saved(paths: &CollectionPaths) -> io::Result<()> {
+        IvfIndex::remove_files(&paths.ivf)
+    }
+    fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &IvfParams, paths: &CollectionPaths) -> Result<Self, VFSError> {
+        Ok(IvfIndex::train(distance_method, vectors, params, &paths.ivf)?)
+    }
+    // También si la colección ha crecido mucho desde que se entrenó.
+    fn needs_training(&self, distance_method: DistanceMethod, params: &IvfParams) -> bool {
+        self.distance_method() != distance_method
+            || !self.trained_with(params)
+            || self.trained_len() == 0
+            || self.len() > IVF_RETRAIN_GROWTH * self.trained_len()
+    }
+    fn insert_vector(&mut self, vector: &VFSVector) -> io::Result<()> {
+        self.insert(vector)
+    }
+    fn remove_vector(&mut self, id: u64) {
+        self.remove(id);
+    }
+    fn len(&self) -> usize {
+        IvfIndex::len(self)
+    }
+}
+
+// Los códigos del IVF-PQ están en memoria y se guardan enteros en `paths.ivf_pq`.
+impl TrainedIndex for IvfPqIndex {
+    type Params = IvfParams;
+    const NAME: &'static str = "IVF-PQ";
+    fn saved_path(paths: &CollectionPaths) -> String {
+        paths.ivf_pq.clone()
+    }
+    fn remove_saved(paths: &CollectionPaths) -> io::Result<()> {
+        remove_index_file(&paths.ivf_pq)
+    }
+    fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &IvfParams, _paths: &CollectionPaths) -> Result<Self, VFSError> {
+        Ok(IvfPqIndex::train(distance_method, vectors, params))
+    }
+    fn needs_training(&self, distance_method: DistanceMethod, params: &IvfParams) -> bool {
+        self.distance_method() != distance_method
+            || !self.trained_with(params)
+            || self.trained_len() == 0
+            || self.len() > IVF_RETRAIN_GROWTH * self.trained_len()
+    }
+    fn insert_vector(&mut self, vector: &VFSVector) -> io::Result<()> {
+        self.insert(vector);
+        Ok(())
+    }
+    fn remove_vector(&mut self, id: u64) {
+        self.remove(id);
+    }
+    fn len(&self) -> usize {
+        IvfPqIndex::len(self)
+    }
+}
 
+// LSH solo se usa con la distancia coseno, así que no depende de la métrica de la búsqueda.
+impl TrainedIndex for LshIndex {
+    type Params = LshParams;
+    const NAME: &'static str = "LSH";
+    fn saved_path(paths: &CollectionPaths) -> String {
+        paths.lsh.clone()
+    }
+    fn remove_saved(paths: &CollectionPaths) -> io::Result<()> {
+        remove_index_file(&paths.lsh)
+    }
+    fn train(_distance_method: DistanceMethod, vectors: &[VFSVector], params: &LshParams, _paths: &CollectionPaths) -> Result<Self, VFSError> {
+        let dimension = vectors.first().map_or(0, |vector| vector.view().len());
+        let mut index = LshIndex::new(*params, dimension);
+        for vector in vectors {
+            index.insert(vector.id(), vector.view());
+        }
+        Ok(index)
+    }
+    // Sin vectores no se sabe la dimensión de los hiperplanos.
+    fn needs_training(&self, _distance_method: DistanceMethod, params: &LshParams) -> bool {
+        self.params() != params || self.dimension() == 0
+    }
+    fn insert_vector(&mut self, vector: &VFSVector) -> io::Result<()> {
+        self.insert(vector.id(), vector.view());
+        Ok(())
+    }
+    fn remove_vector(&mut self, id: u64) {
+        self.remove(id);
+    }
+    fn len(&self) -> usize {
+        LshIndex::len(self)
+    }
+}
+
+// El grafo se escribe en `paths.vamana_graph` al construirlo; en `paths.vamana` se guarda lo que queda en memoria.
+impl TrainedIndex for VamanaIndex {
+    type Params = VamanaParams;
+    const NAME: &'static str = "Vamana";
+    fn saved_path(paths: &CollectionPaths) -> String {
+        paths.vamana.clone()
+    }
+    // El grafo no depende de los offsets del archivo de datos, así que se conserva mientras el índice en memoria lo use.
+    fn remove_saved(paths: &CollectionPaths) -> io::Result<()> {
+        remove_index_file(&paths.vamana)
+    }
+    fn train(distance_method: DistanceMethod, vectors: &[VFSVector], params: &VamanaParams, paths: &CollectionPaths) -> Result<Self, VFSError> {
+        Ok(VamanaIndex::build(distance_method, vectors, params, &paths.vamana_graph)?)
+    }
+    fn needs_training(&self, distance_method: DistanceMethod, params: &VamanaParams) -> bool {
+        self.distance_method() != distance_method || self.params() != params || self.is_outdated()
+    }
+    fn insert_vector(&mut self, vector: &VFSVector) -> io::Result<()> {
+        self.insert(vector);
+        Ok(())
+    }
+    fn remove_vector(&mut self, id: u64) {
+        self.remove(id);
+    }
+    fn len(&self) -> usize {
+        VamanaIndex::len(self)
+    }
+    fn is_complete(&self) -> bool {
+        self.graph_exists()
+    }
 }
 
 
@@ -56,14 +229,146 @@ impl Default for ResetOptions {
     }
 }
 
+// Resultado de una compactación.
+#[derive(Debug, Serialize)]
+pub struct CompactionReport {
+    pub live_records: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub reclaimed_bytes: u64,
+}
+
+// Estado de una compactación en curso.
+// La compactación se divide en tres fases para no bloquear las lecturas durante toda su duración:
+// 1. `VFSManager::begin_compaction`: toma una foto del índice y del tamaño del archivo de datos.
+// 2. `CompactionPlan::copy_live_records`: copia los registros vivos anteriores a la foto al archivo temporal.
+//    No necesita acceso al manager, así que puede ejecutarse sin mantener el lock del servidor.
+// 3. `VFSManager::finish_compaction`: copia los registros escritos durante la fase 2, sustituye el archivo
+//    de datos de forma atómica (rename) y reconstruye los offsets del índice.
+pub struct CompactionPlan {
+    paths: CollectionPaths,
+    end: usize, // Tamaño del archivo de datos al empezar. El archivo es append-only, así que [0, end) no cambia.
+    index_snapshot: BTreeMap<u64, usize>,
+    deleted_snapshot: BTreeSet<u64>,
+    offsets: BTreeMap<usize, usize>, // offset antiguo -> offset nuevo de cada registro copiado.
+    unindexed: BTreeMap<u64, usize>, // Registros copiados que no estaban en el índice (id -> offset nuevo).
+    live_records: usize,
+}
+
+impl CompactionPlan {
+    // Fase 2: copia al archivo temporal la versión vigente de cada vector anterior a la foto.
+    pub fn copy_live_records(&mut self) -> Result<(), VFSError> {
+        // Para los vectores que no están en el índice la versión vigente es la última que aparece en el archivo.
+        let mut latest_unindexed: BTreeMap<u64, usize> = BTreeMap::new();
+        scan_records(&self.paths.data, 0, self.end, |offset, entry| {
+            if !self.index_snapshot.contains_key(&entry.id()) {
+                latest_unindexed.insert(entry.id(), offset);
+            }
+            Ok(())
+        })?;
+
+        scan_records(&self.paths.data, 0, self.end, |offset, entry| {
+            let id = entry.id();
+            if self.deleted_snapshot.contains(&id) {
+                return Ok(());
+            }
+            let current = self.index_snapshot.get(&id).or_else(|| latest_unindexed.get(&id));
+            if current != Some(&offset) {
+                return Ok(()); // Versión antigua o duplicado.
+            }
+
+            let new_offset = save_vector(&entry, &self.paths.compaction)?;
+            self.offsets.insert(offset, new_offset);
+            if !self.index_snapshot.contains_key(&id) {
+                self.unindexed.insert(id, new_offset);
+            }
+            self.live_records += 1;
+            Ok(())
+        })
+    }
+}
+
+// Recorre los registros de vectores del archivo `path` que empiezan entre los offsets `from` y `to`.
+// Las lápidas se saltan. Falla si algún registro no puede leerse, para no perder datos al compactar.
+fn scan_records<G>(path: &str, from: usize, to: usize, mut visit: G) -> Result<(), VFSError>
+where
+    G: FnMut(usize, VFSVector) -> Result<(), VFSError>,
+{
+    let mut reader = RecordReader::open(path, from, Some(COMPACTION_BUFFER_SIZE))?;
+    for record in reader.vectors() {
+        let (record_offset, entry) = record?;
+        if record_offset >= to {
+            break;
+        }
+        visit(record_offset, entry)?;
+    }
+    Ok(())
+}
+
+// Resultado de reconstruir el índice a partir del archivo de datos.
+#[derive(Debug, Serialize)]
+pub struct RecoveryReport {
+    pub records: usize,
+    pub tombstones: usize,
+    pub skipped_bytes: usize,   // Bytes ilegibles entre registros.
+    pub truncated_bytes: usize, // Bytes de un registro incompleto al final del archivo.
+    pub next_id: u64,
+}
+
+// Tamaño actual del archivo de datos (0 si no existe).
+fn data_file_len(path: &str) -> Result<u64, VFSError> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(VFSError::IoError(e)),
+    }
+}
+
+fn remove_index_file(path: &str) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Guarda un índice IVF, IVF-PQ, LSH o Vamana con la parte del archivo de datos que ya contiene. Si no está construido,
+// borra el que hubiera guardado, que ya no corresponde al archivo.
+fn save_trained_index<I: TrainedIndex>(index: Option<&I>, paths: &CollectionPaths, data_len: u64) -> Result<(), VFSError> {
+    let Some(index) = index else {
+        return Ok(I::remove_saved(paths)?);
+    };
+    let encoded = bincode::serialize(&(data_len, index))
+        .map_err(|e| VFSError::SerializationError(e.to_string()))?;
+    write_file_atomically(&I::saved_path(paths), &encoded)?;
+    println!("Índice {} guardado: {} vectores", I::NAME, index.len());
+    Ok(())
+}
+
 pub struct VFSManager {
     pub name: String,
+    paths: CollectionPaths, // Archivos de la colección.
     index_map: BTreeMap<u64, usize>, // Usamos BTREEMap para asemejar la estructura btree típica de las bases de datos relacionales.
     // Representa un índice id -> offset y perimite realizar búsquedas rápidas por id.
     next_id: u64,
     memtable: IndexMap<u64, VFSVector>, /// Uso indexmap en vez de hashmap por que respeta el orden y unicidad. Funciona mejor
     current_offset: usize,
-    quantize: bool // Si hay que cuantizar o no
+    quantize: bool, // Si hay que cuantizar o no
+    deleted: BTreeSet<u64>, // Ids borrados. Sus registros siguen en el archivo de datos, pero se ignoran al leer.
+    compacting: bool, // Evita lanzar dos compactaciones a la vez.
+    wal: Option<Wal>, // Write-ahead log de la memtable (opcional).
+    use_mmap: bool, // Leer el archivo de datos a través de mmap en lugar de abrirlo en cada lectura.
+    mmap: Option<MappedReader>, // Proyección actual del archivo de datos (si `use_mmap`).
+    cache: VectorCache, // Buffer pool de lectura (vectores leídos del disco por offset).
+    hnsw: ManagedIndex, // Grafo HNSW de la colección. Se actualiza con cada escritura, así las búsquedas aproximadas no lo reconstruyen.
+    hnsw_distance: DistanceMethod, // Métrica con la que se construye el grafo.
+    index_threads: usize, // Hilos para construir el grafo a partir del archivo de datos.
+    ivf: Option<IvfIndex>, // Índice IVF. Se entrena la primera vez que se busca con él.
+    ivf_pq: Option<IvfPqIndex>, // Índice IVF-PQ. También se entrena en su primera búsqueda.
+    ivf_params: IvfParams, // Parámetros de los dos índices IVF.
+    lsh: Option<LshIndex>, // Índice LSH (distancia coseno). Se construye la primera vez que se busca con él.
+    lsh_params: LshParams,
+    vamana: Option<VamanaIndex>, // Grafo Vamana en disco. Se construye la primera vez que se busca con él.
+    vamana_params: VamanaParams,
 }
 
 impl VFSManager {
@@ -72,27 +377,47 @@ impl VFSManager {
         
         VFSManager {
             name: name.to_string(),
+            paths: CollectionPaths::new(name),
             index_map: BTreeMap::new(),
             next_id: 1,
             memtable: IndexMap::new(),
             current_offset: 0,
-            quantize: quantize.unwrap_or(false)
+            quantize: quantize.unwrap_or(false),
+            deleted: BTreeSet::new(),
+            compacting: false,
+            wal: None,
+            use_mmap: false,
+            mmap: None,
+            cache: VectorCache::new(DEFAULT_CACHE_BYTES),
+            hnsw: ManagedIndex::new(distance_fn(&DEFAULT_DISTANCE_METHOD), HnswParams::default()),
+            hnsw_distance: DEFAULT_DISTANCE_METHOD,
+            index_threads: default_build_threads(),
+            ivf: None,
+            ivf_pq: None,
+            ivf_params: IvfParams::default(),
+            lsh: None,
+            lsh_params: LshParams::default(),
+            vamana: None,
+            vamana_params: VamanaParams::default(),
         }
     }
 
     pub fn reset_state(&mut self, options: ResetOptions) -> io::Result<()> {  // Añadido el tipo de retorno
        // Manejar el archivo de datos según las opciones
         if options.truncate_data_file {
-            let file_path = options.storage_path.unwrap_or(STORAGE_PATH);
+            let file_path = options.storage_path.map(str::to_string).unwrap_or_else(|| self.paths.data.clone());
         
-            {
-                let file = OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(file_path)?;
-             // El archivo se cierra automáticamente al final de este bloque
-            }
+            // El archivo queda vacío, solo con la cabecera del formato.
+            self.invalidate_read_path();
+            create_data_file(&file_path)?;
+            // El grafo y los demás índices guardados ya no corresponden a este archivo.
+            self.remove_hnsw_file()?;
+            self.ivf = None;
+            self.ivf_pq = None;
+            self.lsh = None;
+            self.vamana = None;
+            self.remove_trained_index_files()?;
+            remove_index_file(&self.paths.vamana_graph)?;
         
              println!("Archivo de datos truncado en: {}", file_path);
         }
@@ -107,7 +432,14 @@ impl VFSManager {
         }
 
         if options.clear_indexmap {
+            // Las lápidas solo tienen sentido junto al índice.
             self.index_map = BTreeMap::new();
+            self.deleted = BTreeSet::new();
+            self.hnsw = self.empty_hnsw();
+            self.ivf = None;
+            self.ivf_pq = None;
+            self.lsh = None;
+            self.vamana = None;
         }
     
         if options.reset_id_counter {
@@ -117,12 +449,378 @@ impl VFSManager {
         if options.reset_quantize {
             self.quantize = false
         }
+
+        if options.clear_memtable || options.reset_id_counter {
+            self.checkpoint_wal()?;
+        }
     
         
         
         Ok(())  
     }
 
+    // Activa o desactiva la lectura del archivo de datos mediante mmap.
+    pub fn set_mmap(&mut self, enabled: bool) {
+        self.use_mmap = enabled;
+        self.mmap = None;
+    }
+
+    // Cambia el presupuesto de la caché de lectura (0 la desactiva). Se vacía la caché actual.
+    pub fn set_cache_bytes(&mut self, capacity_bytes: usize) {
+        self.cache = VectorCache::new(capacity_bytes);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    // Cambia la métrica y los parámetros del grafo HNSW. El grafo se vacía, así que hay que llamarlo antes de insertar o recuperar vectores.
+    pub fn set_index_config(&mut self, distance_method: DistanceMethod, params: HnswParams) {
+        self.hnsw = ManagedIndex::new(distance_fn(&distance_method), params);
+        self.hnsw_distance = distance_method;
+    }
+
+    // Hilos con los que se reconstruye el grafo desde el archivo de datos (al restaurar o tras compactar).
+    pub fn set_index_threads(&mut self, threads: usize) {
+        self.index_threads = threads.max(1);
+    }
+
+    pub fn index_params(&self) -> &HnswParams {
+        self.hnsw.params()
+    }
+
+    pub fn index_stats(&self) -> IndexStats {
+        self.hnsw.stats()
+    }
+
+    // Grafo vacío con la configuración actual.
+    fn empty_hnsw(&self) -> ManagedIndex {
+        ManagedIndex::new(distance_fn(&self.hnsw_distance), *self.hnsw.params())
+    }
+
+    // Consulta los `k` vecinos más cercanos en el grafo HNSW. Devuelve `None` si el grafo se construyó con otra métrica.
+    pub fn ann_query(&mut self, query: &VFSVector, distance_method: &DistanceMethod, k: usize, ef_search: usize) -> io::Result<Option<Vec<(u64, f32)>>> {
+        if *distance_method != self.hnsw_distance {
+            return Ok(None);
+        }
+        let results = self.hnsw.query(query, k, ef_search)?;
+        Ok(Some(results.iter().map(|(vector, distance)| (vector.id(), *distance)).collect()))
+    }
+
+    // Añade un vector (o su nueva versión) a los índices de búsqueda aproximada.
+    fn index_vector(&mut self, vector: &VFSVector) -> io::Result<()> {
+        self.hnsw.insert_one(vector.clone());
+        if let Some(ivf_pq) = self.ivf_pq.as_mut() {
+            ivf_pq.insert(vector);
+        }
+        if let Some(lsh) = self.lsh.as_mut() {
+            lsh.insert(vector.id(), vector.view());
+        }
+        if let Some(vamana) = self.vamana.as_mut() {
+            vamana.insert(vector);
+        }
+        if let Some(ivf) = self.ivf.as_mut() {
+            ivf.insert(vector)?;
+        }
+        Ok(())
+    }
+
+    fn unindex_vector(&mut self, id: u64) {
+        self.hnsw.remove(id);
+        if let Some(ivf) = self.ivf.as_mut() {
+            ivf.remove(id);
+        }
+        if let Some(ivf_pq) = self.ivf_pq.as_mut() {
+            ivf_pq.remove(id);
+        }
+        if let Some(lsh) = self.lsh.as_mut() {
+            lsh.remove(id);
+        }
+        if let Some(vamana) = self.vamana.as_mut() {
+            vamana.remove(id);
+        }
+    }
+
+    // Cambia los parámetros IVF. Los índices actuales se descartan y se entrenan con ellos en la próxima búsqueda.
+    pub fn set_ivf_params(&mut self, params: IvfParams) {
+        self.ivf_params = params;
+        self.ivf = None;
+        self.ivf_pq = None;
+    }
+
+    pub fn ivf_params(&self) -> &IvfParams {
+        &self.ivf_params
+    }
+
+    // `None` si el índice IVF aún no se ha entrenado.
+    pub fn ivf_stats(&self) -> Option<IvfStats> {
+        self.ivf.as_ref().map(IvfIndex::stats)
+    }
+
+    pub fn ivf_pq_stats(&self) -> Option<IvfPqStats> {
+        self.ivf_pq.as_ref().map(IvfPqIndex::stats)
+    }
+
+    // Consulta los `k` vecinos más cercanos en el índice IVF recorriendo `nprobe` listas (por defecto, las de la colección).
+    // El índice se entrena antes si no existe, usa otra métrica u otro `nlist`, o la colección ha crecido mucho desde que se entrenó.
+    pub fn ivf_query(&mut self, query: &VFSVector, distance_method: &DistanceMethod, k: usize, nprobe: Option<usize>) -> Result<Vec<(u64, f32)>, VFSError> {
+        let params = self.ivf_params;
+        if self.ivf.as_ref().is_none_or(|ivf| ivf.needs_training(*distance_method, &params)) {
+            // Entrenar reescribe las listas, así que el índice actual se descarta antes.
+            self.ivf = None;
+            self.ivf = Some(self.train_index(*distance_method, &params)?);
+        }
+
+        let nprobe = nprobe.unwrap_or(self.ivf_params.nprobe);
+        let candidates = match &self.ivf {
+            Some(ivf) => ivf.candidates(query, nprobe)?,
+            None => Vec::new(),
+        };
+
+        // Las listas solo guardan ids: los vectores se leen de la memtable o del archivo de datos.
+        let distance = distance_fn(distance_method);
+        let mut results = Vec::with_capacity(candidates.len());
+        for id in candidates {
+            if let Some(vector) = self.live_vector(id)? {
+                results.push((id, distance(query, &vector)));
+            }
+        }
+        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    // Como `ivf_query`, pero con el índice IVF-PQ: las distancias salen de los códigos PQ y son aproximadas.
+    // Con `rerank` > 0 se toman max(k, rerank) candidatos y se recalcula su distancia exacta con los vectores guardados.
+    pub fn ivf_pq_query(&mut self, query: &VFSVector, distance_method: &DistanceMethod, k: usize, nprobe: Option<usize>, rerank: usize) -> Result<Vec<(u64, f32)>, VFSError> {
+        let params = self.ivf_params;
+        if self.ivf_pq.as_ref().is_none_or(|ivf_pq| ivf_pq.needs_training(*distance_method, &params)) {
+            self.ivf_pq = None;
+            self.ivf_pq = Some(self.train_index(*distance_method, &params)?);
+        }
+
+        let nprobe = nprobe.unwrap_or(self.ivf_params.nprobe);
+        let candidates = self.ivf_pq.as_ref()
+            .map(|ivf_pq| ivf_pq.query(query, k.max(rerank), nprobe))
+            .unwrap_or_default();
+        if rerank == 0 {
+            return Ok(candidates);
+        }
+
+        let distance = distance_fn(distance_method);
+        let mut results = Vec::with_capacity(candidates.len());
+        for (id, _) in candidates {
+            if let Some(vector) = self.live_vector(id)? {
+                results.push((id, distance(query, &vector)));
+            }
+        }
+        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(k);
+        Ok(results)
+    }
+
+    // Cambia los parámetros LSH. El índice actual se descarta y se construye con ellos en la próxima búsqueda.
+    pub fn set_lsh_params(&mut self, params: LshParams) {
+        self.lsh_params = params;
+        self.lsh = None;
+    }
+
+    pub fn lsh_params(&self) -> &LshParams {
+        &self.lsh_params
+    }
+
+    // `None` si el índice LSH aún no se ha construido.
+    pub fn lsh_stats(&self) -> Option<LshStats> {
+        self.lsh.as_ref().map(LshIndex::stats)
+    }
+
+    // Ids que comparten cubo con `query` en alguna tabla LSH, visitando `probes` cubos vecinos por tabla
+    // (por defecto, los de la colección). Las distancias las calcula quien llama.
+    pub fn lsh_candidates(&mut self, query: &VFSVector, probes: Option<usize>) -> Result<Vec<u64>, VFSError> {
+        let params = self.lsh_params;
+        if self.lsh.as_ref().is_none_or(|lsh| lsh.needs_training(DistanceMethod::Cosine, &params)) {
+            self.lsh = None;
+            self.lsh = Some(self.train_index(DistanceMethod::Cosine, &params)?);
+        }
+
+        let probes = probes.unwrap_or(params.probes);
+        Ok(self.lsh.as_ref().map(|lsh| lsh.candidates(query.view(), probes)).unwrap_or_default())
+    }
+
+    // Cambia los parámetros del grafo Vamana. El grafo actual se descarta y se construye con ellos en la próxima búsqueda.
+    pub fn set_vamana_params(&mut self, params: VamanaParams) {
+        self.vamana_params = params;
+        self.vamana = None;
+    }
+
+    pub fn vamana_params(&self) -> &VamanaParams {
+        &self.vamana_params
+    }
+
+    // `None` si el grafo Vamana aún no se ha construido.
+    pub fn vamana_stats(&self) -> Option<VamanaStats> {
+        self.vamana.as_ref().map(VamanaIndex::stats)
+    }
+
+    // Consulta los `k` vecinos más cercanos en el grafo Vamana con una lista de `search_list` candidatos.
+    // El grafo se construye antes si no existe, usa otra métrica u otros parámetros, o han cambiado demasiados vectores.
+    pub fn vamana_query(&mut self, query: &VFSVector, distance_method: &DistanceMethod, k: usize, search_list: usize) -> Result<Vec<(u64, f32)>, VFSError> {
+        let params = self.vamana_params;
+        if self.vamana.as_ref().is_none_or(|vamana| vamana.needs_training(*distance_method, &params)) {
+            // Construirlo sobrescribe el grafo, así que el índice actual se descarta antes.
+            self.vamana = None;
+            self.vamana = Some(self.train_index(*distance_method, &params)?);
+        }
+
+        Ok(self.vamana.as_ref().map(|vamana| vamana.query(query, k, search_list)).transpose()?.unwrap_or_default())
+    }
+
+    // Construye un índice IVF, IVF-PQ, LSH o Vamana con todos los vectores vigentes y lo guarda, para no repetir el entrenamiento
+    // al reiniciar. El índice guardado se borra antes: si el proceso se cae a medias, no queda ninguno.
+    fn train_index<I: TrainedIndex>(&mut self, distance_method: DistanceMethod, params: &I::Params) -> Result<I, VFSError> {
+        I::remove_saved(&self.paths)?;
+        let vectors = self.live_vectors()?;
+        println!("Entrenando el índice {} con {} vectores", I::NAME, vectors.len());
+        let index = I::train(distance_method, &vectors, params, &self.paths)?;
+        println!("Índice {} listo: {} vectores", I::NAME, index.len());
+        // Los vectores de la memtable no están en el archivo de datos, pero el WAL los vuelve a añadir al reiniciar.
+        save_trained_index(Some(&index), &self.paths, data_file_len(&self.paths.data)?)?;
+        Ok(index)
+    }
+
+    // Versión vigente de un vector para calcular distancias, descuantizada. `None` si está borrado.
+    fn live_vector(&mut self, id: u64) -> Result<Option<VFSVector>, VFSError> {
+        if self.deleted.contains(&id) {
+            return Ok(None);
+        }
+        if let Some(vector) = self.memtable.get(&id) {
+            return Ok(Some(vector.clone()));
+        }
+        let Some(&offset) = self.index_map.get(&id) else {
+            return Ok(None);
+        };
+        let vector = self.load_vector_at_offset(offset)?;
+        Ok(Some(if self.quantize { vector.dequantize()? } else { vector }))
+    }
+
+    // Versión vigente de cada vector, tanto del archivo de datos como de la memtable, sin cambiar el offset de lectura.
+    fn live_vectors(&mut self) -> Result<Vec<VFSVector>, VFSError> {
+        let mut vectors: BTreeMap<u64, VFSVector> = BTreeMap::new();
+        let mut reader = RecordReader::open(&self.paths.data, FILE_HEADER_LEN, Some(COMPACTION_BUFFER_SIZE))?;
+        for record in reader.by_ref() {
+            if let (offset, Record::Vector(vector)) = record? {
+                if self.is_live_record(offset, vector.id()) {
+                    let vector = if self.quantize { vector.dequantize()? } else { vector };
+                    vectors.insert(vector.id(), vector);
+                }
+            }
+        }
+        // Un vector de la memtable puede ser más reciente que su registro (upsert antes del volcado).
+        for (id, vector) in &self.memtable {
+            vectors.insert(*id, vector.clone());
+        }
+        Ok(vectors.into_values().collect())
+    }
+
+    // Descarta todo lo que depende de los offsets del archivo de datos actual.
+    // Hay que llamarlo antes de truncar o sustituir el archivo.
+    fn invalidate_read_path(&mut self) {
+        self.mmap = None;
+        self.cache.clear();
+    }
+
+    // Vuelve a proyectar el archivo de datos si ha cambiado de tamaño (por ejemplo tras un flush).
+    fn refresh_mmap(&mut self) -> Result<(), VFSError> {
+        if !self.use_mmap {
+            return Ok(());
+        }
+
+        let file_len = data_file_len(&self.paths.data)? as usize;
+        let stale = match &self.mmap {
+            Some(mapped) => mapped.len() != file_len,
+            None => true,
+        };
+        if stale {
+            // No se puede proyectar un archivo vacío. Mientras tanto se lee sin mmap.
+            self.mmap = if file_len > 0 { Some(MappedReader::open(&self.paths.data)?) } else { None };
+        }
+        Ok(())
+    }
+
+    // Asocia un WAL al manager. A partir de aquí cada cambio en la memtable se registra en el log.
+    pub fn attach_wal(&mut self, wal: Wal) {
+        self.wal = Some(wal);
+    }
+
+    // Añade una entrada al WAL, si hay uno asociado.
+    fn log(&mut self, entry: WalEntry) -> Result<(), VFSError> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&entry)?;
+        }
+        Ok(())
+    }
+
+    // Reescribe el WAL con lo que sigue en la memtable y el siguiente id.
+    // Lo que ya se volcó al archivo de datos no necesita seguir en el log.
+    fn checkpoint_wal(&mut self) -> Result<(), VFSError> {
+        if let Some(wal) = self.wal.as_mut() {
+            let mut entries = Vec::with_capacity(self.memtable.len() + 1);
+            entries.push(WalEntry::Checkpoint { next_id: self.next_id });
+            entries.extend(self.memtable.values().cloned().map(WalEntry::Insert));
+            wal.rewrite(&entries)?;
+        }
+        Ok(())
+    }
+
+    // Reproduce las entradas de un WAL para reconstruir la memtable tras un crash.
+    // Devuelve el número de vectores recuperados en la memtable.
+    pub fn replay_wal(&mut self, entries: Vec<WalEntry>) -> usize {
+        for entry in entries {
+            match entry {
+                WalEntry::Checkpoint { next_id } => {
+                    self.next_id = self.next_id.max(next_id);
+                }
+                WalEntry::Insert(vector) => {
+                    let id = vector.id();
+                    self.next_id = self.next_id.max(id + 1);
+                    self.deleted.remove(&id);
+                    self.replay_index(&vector);
+                    self.memtable.insert(id, vector);
+                }
+                WalEntry::Upsert(vector) => {
+                    // La nueva versión ya está en el archivo de datos, solo hay que actualizar la memtable.
+                    let id = vector.id();
+                    self.next_id = self.next_id.max(id + 1);
+                    self.deleted.remove(&id);
+                    self.replay_index(&vector);
+                    if let Some(entry) = self.memtable.get_mut(&id) {
+                        *entry = vector;
+                    }
+                }
+                WalEntry::Delete(id) => {
+                    self.memtable.shift_remove(&id);
+                    self.index_map.remove(&id);
+                    self.unindex_vector(id);
+                    self.deleted.insert(id);
+                }
+            }
+        }
+        println!("WAL reproducido: {} vectores en memtable, siguiente id {}", self.memtable.len(), self.next_id);
+        self.memtable.len()
+    }
+
+    // Añade a los índices un vector del WAL. Si no se puede escribir en las listas IVF, el índice IVF se descarta
+    // (se volverá a entrenar en la próxima búsqueda IVF) en lugar de interrumpir la recuperación de la memtable.
+    fn replay_index(&mut self, vector: &VFSVector) {
+        if let Err(e) = self.index_vector(vector) {
+            println!("No se pudo añadir el vector {} al índice IVF, se descarta: {}", vector.id(), e);
+            self.ivf = None;
+            if let Err(e) = <IvfIndex as TrainedIndex>::remove_saved(&self.paths) {
+                println!("No se pudo borrar el índice IVF: {}", e);
+            }
+        }
+    }
+
     fn next_id(&mut self) -> Result<u64, VFSError> {
         let aux = self.next_id;
         self.next_id += 1;
@@ -136,26 +834,41 @@ impl VFSManager {
     }
   
 
-    fn flush_memtable_to_disk(&mut self) -> Result<(), VFSError> {
-        for (id, mut vector) in self.memtable.drain(..) {
-            let mut offset = 0;
-            
-            if self.quantize {
-                offset = match vector.quantize() {
-                    Ok(quantized) => save_vector(&quantized, STORAGE_PATH).map_err(VFSError::IoError)?,
-                    Err(e) => return Err(VFSError::InvalidVector(format!("Error: {}", e))),
-                };
-                
-                
-            } else {
-                offset = save_vector(&vector, STORAGE_PATH).map_err(VFSError::IoError)?;
+    // Escribe un vector en el archivo de datos, cuantizándolo antes si el manager lo requiere.
+    // Devuelve el offset donde se guardó.
+    fn write_vector(&self, vector: &VFSVector) -> Result<usize, VFSError> {
+        if self.quantize {
+            match vector.quantize() {
+                Ok(quantized) => save_vector(&quantized, &self.paths.data).map_err(VFSError::IoError),
+                Err(e) => Err(VFSError::InvalidVector(format!("Error: {}", e))),
             }
-        
+        } else {
+            save_vector(vector, &self.paths.data).map_err(VFSError::IoError)
+        }
+    }
+
+    fn flush_memtable_to_disk(&mut self) -> Result<(), VFSError> {
+        let drained: Vec<(u64, VFSVector)> = self.memtable.drain(..).collect();
+        for (id, vector) in drained {
+            let offset = self.write_vector(&vector)?;
             self.index_map.insert(id, offset); // Indexar los vectores
         }
+        self.checkpoint_wal()?;
         Ok(())
     }
 
+    // Un registro del archivo de datos es la versión vigente de su vector si no fue borrado y el índice
+    // no apunta a otra versión más reciente (escrita por `upsert_vector`).
+    fn is_live_record(&self, offset: usize, id: u64) -> bool {
+        if self.deleted.contains(&id) {
+            return false;
+        }
+        match self.index_map.get(&id) {
+            Some(&indexed) => indexed == offset,
+            None => true,
+        }
+    }
+
     pub fn flush_manual(&mut self) -> Result<(), VFSError> {
         self.flush_memtable_to_disk().map_err(|e| VFSError::MemtableError(e.to_string()))?;
         Ok(())
@@ -171,59 +884,87 @@ impl VFSManager {
             let to_extract = count.min(self.memtable.len());
             
         
-            for (id, vector) in self.memtable.drain(..to_extract) {
-                let mut offset = 0;
-                // Esto no es lo más eficiente pero me ocurre lo mismo que en el otro iterador
-                let original = vector.clone();
-            
-                if self.quantize {
-                    
-                    offset = match vector.quantize() { // Vector se consume aquí
-                        Ok(quantized) => save_vector(&quantized, STORAGE_PATH)?,
-                        Err(e) => return Err(e),
-                    };
-                    
-                } else {
-                    offset = save_vector(&vector, STORAGE_PATH)?;
-                }
-            
+            let drained: Vec<(u64, VFSVector)> = self.memtable.drain(..to_extract).collect();
+            for (id, vector) in drained {
+                let offset = self.write_vector(&vector)?;
                 self.index_map.insert(id, offset);
-                batch.push(original); // guardamos el vector en la lista.
+                batch.push(vector); // guardamos el vector en la lista.
             }
+            self.checkpoint_wal()?;
 
             
         }
 
         // P2: Si aun no se alcanzó la cantidad requerida, cargar desde disco.
+        // Los vectores borrados y las versiones antiguas se descartan, así que seguimos leyendo hasta completar el lote o llegar al final del archivo.
         if batch.len() < count {
-            let needed = count - batch.len();
-            let (mut entries, new_offset) = load_vectors(STORAGE_PATH, self.current_offset, needed, None)?;
-
-            if self.quantize {
-                for i in 0..entries.len() {
-                    // Tomamos ownership temporalmente
-                    // El sistema de tipos de rust hace que sea complicado realizar las modificaciones in-place, 
-                    // Esta opción es la más elegante que he sabido implementar.
-                    // Como dequantize destruye el vector antiguo puedo hacer esto.
-                    entries[i] = entries[i].dequantize()?;
+            self.refresh_mmap()?;
+            self.current_offset = match &self.mmap {
+                Some(mapped) => {
+                    let mut records = mapped.records(self.current_offset);
+                    self.fill_batch(records.by_ref(), &mut batch, count)?;
+                    records.offset()
                 }
-            } 
-           
-            self.current_offset = new_offset;
-            batch.append(&mut entries);
+                None => {
+                    let mut reader = RecordReader::open(&self.paths.data, self.current_offset, None)?;
+                    self.fill_batch(reader.by_ref(), &mut batch, count)?;
+                    reader.offset()
+                }
+            };
         }
 
         Ok(batch)
     }
 
-    fn load_vector_at_offset(&self, offset: usize) -> Result<(VFSVector), VFSError> {
-        // Carga un único vector en el offset especificado.
-        let (vec, _) = load_vectors(STORAGE_PATH, offset, 1, None)?;
-        if vec.len() == 0 {
-            println!("No había vectores en ese offset");
-            return Err(VFSError::InvalidVector("No vectors at the specified offset".to_string()))
+    // Añade a `batch` los vectores vivos de `records` hasta tener `count`.
+    // Sirve tanto para `RecordReader` como para la lectura con mmap.
+    fn fill_batch<I>(&self, records: I, batch: &mut Vec<VFSVector>, count: usize) -> Result<(), VFSError>
+    where
+        I: Iterator<Item = Result<(usize, Record), VFSError>>,
+    {
+        for record in records {
+            let (offset, entry) = match record? {
+                (offset, Record::Vector(entry)) => (offset, entry),
+                (_, Record::Tombstone(_)) => continue,
+            };
+            if !self.is_live_record(offset, entry.id()) {
+                continue;
+            }
+
+            // Los vectores cuantizados se devuelven siempre en f32.
+            let entry = if self.quantize { entry.dequantize()? } else { entry };
+            batch.push(entry);
+            if batch.len() >= count {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn load_vector_at_offset(&mut self, offset: usize) -> Result<(VFSVector), VFSError> {
+        // Carga un único vector en el offset especificado, pasando primero por la caché de lectura.
+        if let Some(vector) = self.cache.get(offset) {
+            return Ok(vector);
+        }
+
+        self.refresh_mmap()?;
+        let record = match &self.mmap {
+            Some(mapped) => mapped.read_record(offset)?,
+            None => {
+                let mut reader = RecordReader::open(&self.paths.data, offset, None)?;
+                reader.next_record()?.map(|(_, record)| (record, reader.offset()))
+            }
+        };
+        match record {
+            Some((Record::Vector(vector), next_offset)) => {
+                self.cache.insert(offset, vector.clone(), next_offset - offset);
+                Ok(vector)
+            }
+            _ => {
+                println!("No había vectores en ese offset");
+                Err(VFSError::InvalidVector("No vectors at the specified offset".to_string()))
+            }
         }
-        return Ok(vec[0].clone())
     }
 
     pub fn get_max_id(&self) -> u64 {
@@ -233,6 +974,12 @@ impl VFSManager {
 
     // Esto es muy ineficiente, mejorarlo para no hacer la búsqueda lineal.
     pub fn get_vector_by_id(&mut self, id: u64) -> Option<VFSVector> {
+        // Paso 0. Los vectores borrados no se devuelven nunca.
+        if self.deleted.contains(&id) {
+            println!("El vector con ID {} fue borrado", id);
+            return None;
+        }
+
         // Paso 1. Mirar si el vector está en la memtable.
         if let Some(vector) = self.memtable.get(&id) {
             println!("Vector encontrado en memtable con ID: {}", id);
@@ -277,7 +1024,6 @@ impl VFSManager {
         
             if vector.id() == id {
                 println!("Vector encontrado con ID: {}", id);
-                self.vector_to_memtable(vector.clone());
                 result = Some(vector.clone());
                 break;
             }
@@ -299,12 +1045,75 @@ impl VFSManager {
         self.memtable.len()
     }
 
+    pub fn paths(&self) -> &CollectionPaths {
+        &self.paths
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quantize
+    }
+
     pub fn get_total_vectors_estimate(&self) -> usize {
-        self.next_id as usize - 1
+        self.next_id as usize - 1 - self.deleted.len()
+    }
+
+    pub fn is_deleted(&self, id: u64) -> bool {
+        self.deleted.contains(&id)
+    }
+
+    // Borra un vector.
+    // Como el archivo de datos es append-only, se escribe una lápida con el id borrado y se elimina
+    // el id del índice y de la memtable. Las lecturas posteriores (load_batch, búsquedas, get_vector_by_id) lo ignoran.
+    pub fn delete_vector(&mut self, id: u64) -> Result<(), VFSError> {
+        if id == 0 || id >= self.next_id || self.deleted.contains(&id) {
+            return Err(VFSError::NotFound(format!("Vector with id {} does not exist", id)));
+        }
+
+        save_tombstone(id, &self.paths.data)?;
+        self.log(WalEntry::Delete(id))?;
+
+        self.index_map.remove(&id);
+        self.memtable.shift_remove(&id);
+        self.unindex_vector(id);
+        self.deleted.insert(id);
+        println!("Vector con ID {} borrado", id);
+
+        Ok(())
+    }
+
+    // Inserta o actualiza el vector con identificador `id`.
+    // La nueva versión se añade al final del archivo de datos y el índice pasa a apuntar a ella,
+    // de modo que las versiones anteriores quedan ocultas para las búsquedas y para `get_vector_by_id`.
+    // Si el vector estaba en la memtable, se sustituye también allí.
+    // Solo admite ids ya asignados (un id borrado se vuelve a crear): los ids nuevos los da `next_id`.
+    pub fn upsert_vector(&mut self, id: u64, data: Vec<f32>, name: &str, tags: Vec<String>) -> Result<u64, VFSError> {
+        if data.is_empty() {
+            return Err(VFSError::InvalidVector("Vector data cannot be empty".to_string()));
+        }
+        if id == 0 || id >= self.next_id {
+            return Err(VFSError::NotFound(format!("Vector with id {} does not exist", id)));
+        }
+
+        let vfs = VFSVector::from_vec(data, id, name, tags);
+        let offset = self.write_vector(&vfs)?;
+        self.log(WalEntry::Upsert(vfs.clone()))?;
+        self.index_map.insert(id, offset);
+        self.index_vector(&vfs)?;
+
+        if let Some(entry) = self.memtable.get_mut(&id) {
+            *entry = vfs;
+        }
+
+        // Un upsert sobre un id borrado lo vuelve a crear.
+        self.deleted.remove(&id);
+        println!("VFSVector con ID {} actualizado correctamente", id);
+
+        Ok(id)
     }
 
     fn vector_to_memtable(&mut self, vector: VFSVector) -> Result<(), VFSError>{
         let id = vector.id();
+        self.index_vector(&vector)?;
         self.memtable.insert(id, vector);
 
         if self.memtable.len() >= FLUSH_THRESHOLD {
@@ -325,6 +1134,9 @@ impl VFSManager {
 
         // Crear el vector VFS
         let vfs = VFSVector::from_vec(data, id, name, tags);
+
+        // Registrarlo en el WAL antes de confirmarlo
+        self.log(WalEntry::Insert(vfs.clone()))?;
         
         // Guardarlo en la memtable
         self.vector_to_memtable(vfs).map_err(|e| VFSError::MemtableError(format!("Error saving vector to memtable: {}", e)))?;
@@ -354,6 +1166,9 @@ impl VFSManager {
         // Crear el vector VFS
         let vfs = VFSVector::from_simd(data, id, name, tags, quantize, scale_factor);
 
+        // Registrarlo en el WAL antes de confirmarlo
+        self.log(WalEntry::Insert(vfs.clone()))?;
+
         // Guardarlo en la memtable
         self.vector_to_memtable(vfs)
         .map_err(|e| VFSError::MemtableError(format!("Error saving vector to memtable: {}", e)))?;
@@ -365,19 +1180,24 @@ impl VFSManager {
     
     // Realiza un snapshot del estado actual del manager y lo graba en disco.
     pub fn save_state(&mut self, path: Option<&'static str>) -> Result<(), VFSError> {
-        let fpath = path.unwrap_or(VFS_STATE_PATH);
+        let fpath = path.map(str::to_string).unwrap_or_else(|| self.paths.state.clone());
+        let fpath = fpath.as_str();
         // Crear el directorio si no existe
         if let Some(parent) = std::path::Path::new(fpath).parent() {
             std::fs::create_dir_all(parent)?;
         }
 
+        // Guardar la memtable antes, para que el índice del snapshot incluya sus vectores
+        self.flush_memtable_to_disk().map_err(|e| VFSError::MemtableError(e.to_string()))?;
+
         let state = VFSState {
             next_id: self.next_id,
             name: self.name.clone(),
             current_offset: self.current_offset,
             index_map: self.index_map.clone(),
             quantize: self.quantize,
-            
+            deleted: self.deleted.clone(),
+            data_len: data_file_len(&self.paths.data)?,
         };
 
         let encoded: Vec<u8> = bincode::serialize(&state)
@@ -401,28 +1221,411 @@ impl VFSManager {
             }
         }
 
-        
+        self.save_hnsw(state.data_len)?;
+        save_trained_index(self.ivf.as_ref(), &self.paths, state.data_len)?;
+        save_trained_index(self.ivf_pq.as_ref(), &self.paths, state.data_len)?;
+        save_trained_index(self.lsh.as_ref(), &self.paths, state.data_len)?;
+        save_trained_index(self.vamana.as_ref(), &self.paths, state.data_len)
+    }
 
-        // Guardar la memtable
-        self.flush_memtable_to_disk().map_err(|e| VFSError::MemtableError(e.to_string()))?;
+    fn remove_trained_index_files(&self) -> io::Result<()> {
+        <IvfIndex as TrainedIndex>::remove_saved(&self.paths)?;
+        <IvfPqIndex as TrainedIndex>::remove_saved(&self.paths)?;
+        <LshIndex as TrainedIndex>::remove_saved(&self.paths)?;
+        <VamanaIndex as TrainedIndex>::remove_saved(&self.paths)
+    }
 
+    // Carga los índices IVF, IVF-PQ, LSH y Vamana guardados. Los que no existan o no correspondan al archivo de datos
+    // se construirán en su próxima búsqueda.
+    fn restore_trained_indexes(&mut self) -> Result<(), VFSError> {
+        self.ivf = None;
+        self.ivf_pq = None;
+        self.lsh = None;
+        self.vamana = None;
+        self.ivf = self.load_trained_index()?;
+        self.ivf_pq = self.load_trained_index()?;
+        self.lsh = self.load_trained_index()?;
+        self.vamana = self.load_trained_index()?;
         Ok(())
     }
 
-    pub fn load_state(&mut self, path: Option<&'static str>) -> Result<(), VFSError> {
-        let fpath = path.unwrap_or(VFS_STATE_PATH);
+    // Carga un índice guardado y le añade los registros escritos después de guardarlo.
+    fn load_trained_index<I: TrainedIndex>(&self) -> Result<Option<I>, VFSError> {
+        let data_len = data_file_len(&self.paths.data)?;
+        let saved = match std::fs::read(I::saved_path(&self.paths)) {
+            Ok(bytes) => bincode::deserialize::<(u64, I)>(&bytes).ok(),
+            Err(_) => None,
+        };
+        let Some((saved_len, mut index)) = saved.filter(|(saved_len, index)| *saved_len <= data_len && index.is_complete()) else {
+            return Ok(None);
+        };
+
+        let from = (saved_len as usize).max(FILE_HEADER_LEN);
+        if (from as u64) < data_len {
+            let mut reader = RecordReader::open(&self.paths.data, from, Some(COMPACTION_BUFFER_SIZE))?;
+            for record in reader.by_ref() {
+                match record {
+                    Ok((_, Record::Vector(vector))) => {
+                        let vector = if self.quantize { vector.dequantize()? } else { vector };
+                        index.insert_vector(&vector)?;
+                    }
+                    Ok((_, Record::Tombstone(id))) => index.remove_vector(id),
+                    Err(e) => {
+                        println!("No se pudo poner al día el índice {}: {}", I::NAME, e);
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        println!("Índice {} cargado: {} vectores", I::NAME, index.len());
+        Ok(Some(index))
+    }
+
+    // Guarda el grafo HNSW.
+    fn save_hnsw(&self, data_len: u64) -> Result<(), VFSError> {
+        let hnsw_state = HnswState {
+            distance_method: self.hnsw_distance,
+            data_len,
+            graph: self.hnsw.snapshot(),
+        };
+        let encoded = bincode::serialize(&hnsw_state)
+            .map_err(|e| VFSError::SerializationError(e.to_string()))?;
+        write_file_atomically(&self.paths.hnsw, &encoded)?;
+        println!("Índice HNSW guardado: {} vectores", self.hnsw.len());
+        Ok(())
+    }
+
+    fn remove_hnsw_file(&self) -> io::Result<()> {
+        match std::fs::remove_file(&self.paths.hnsw) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // Carga el grafo HNSW guardado y le añade los registros escritos después de guardarlo.
+    // Si no existe, no se puede leer, usa otra métrica u otros parámetros, o el archivo de datos es más corto de lo que cubre
+    // (se truncó o se compactó), se reconstruye recorriendo todo el archivo de datos.
+    fn restore_hnsw(&mut self) -> Result<(), VFSError> {
+        let data_len = data_file_len(&self.paths.data)?;
+        let saved = std::fs::read(&self.paths.hnsw)
+            .map_err(VFSError::IoError)
+            .and_then(|bytes| bincode::deserialize::<HnswState>(&bytes)
+                .map_err(|e| VFSError::SerializationError(e.to_string())));
+
+        let mut from = FILE_HEADER_LEN;
+        match saved {
+            Ok(saved) if saved.distance_method == self.hnsw_distance && saved.data_len <= data_len => {
+                match ManagedIndex::from_snapshot(distance_fn(&self.hnsw_distance), saved.graph) {
+                    Ok(hnsw) if hnsw.params() == self.hnsw.params() => {
+                        self.hnsw = hnsw;
+                        from = (saved.data_len as usize).max(FILE_HEADER_LEN);
+                    }
+                    Ok(_) => {
+                        println!("El índice HNSW guardado usa otros parámetros, reconstruyéndolo");
+                        self.hnsw = self.empty_hnsw();
+                    }
+                    Err(e) => {
+                        println!("Índice HNSW inválido: {}", e);
+                        self.hnsw = self.empty_hnsw();
+                    }
+                }
+            }
+            Ok(_) => {
+                println!("El índice HNSW guardado no corresponde al archivo de datos, reconstruyéndolo");
+                self.hnsw = self.empty_hnsw();
+            }
+            Err(e) => {
+                println!("No se pudo cargar el índice HNSW: {}", e);
+                self.hnsw = self.empty_hnsw();
+            }
+        }
+
+        if (from as u64) < data_len {
+            if let Err(e) = self.index_records_from(from) {
+                // El grafo guardado no terminaba en un límite de registro: se reconstruye desde el principio.
+                println!("No se pudo ponerse al día desde el offset {}: {}", from, e);
+                self.hnsw = self.empty_hnsw();
+                self.index_records_from(FILE_HEADER_LEN)?;
+            }
+        }
+        println!("Índice HNSW listo: {} vectores", self.hnsw.len());
+        Ok(())
+    }
+
+    // Añade al grafo los registros del archivo de datos a partir de `from`, en orden: cada vector pasa a ser
+    // la versión vigente de su id y cada lápida lo quita de las búsquedas.
+    // Los vectores se agrupan en lotes para construir el grafo con `index_threads` hilos.
+    fn index_records_from(&mut self, from: usize) -> Result<(), VFSError> {
+        let mut reader = RecordReader::open(&self.paths.data, from, Some(COMPACTION_BUFFER_SIZE))?;
+        let mut batch = Vec::with_capacity(INDEX_BATCH_SIZE);
+        for record in reader.by_ref() {
+            match record? {
+                (_, Record::Vector(vector)) => {
+                    let vector = if self.quantize { vector.dequantize()? } else { vector };
+                    batch.push(vector);
+                    if batch.len() == INDEX_BATCH_SIZE {
+                        self.hnsw.insert_many_parallel(std::mem::take(&mut batch), self.index_threads);
+                    }
+                }
+                (_, Record::Tombstone(id)) => {
+                    // La lápida tiene que aplicarse después de los vectores anteriores.
+                    self.hnsw.insert_many_parallel(std::mem::take(&mut batch), self.index_threads);
+                    self.hnsw.remove(id);
+                }
+            }
+        }
+        self.hnsw.insert_many_parallel(batch, self.index_threads);
+        Ok(())
+    }
+
+    fn read_state(&self, path: Option<&str>) -> Result<VFSState, VFSError> {
+        let fpath = path.unwrap_or(&self.paths.state);
         let mut file = File::open(fpath)?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
 
-        let state: VFSState = bincode::deserialize(&buffer)
-        .map_err(|e| VFSError::SerializationError(e.to_string()))?;
+        bincode::deserialize(&buffer)
+        .map_err(|e| VFSError::SerializationError(e.to_string()))
+    }
 
-        self.next_id = state.next_id;
+    pub fn load_state(&mut self, path: Option<&'static str>) -> Result<(), VFSError> {
+        let state = self.read_state(path)?;
+        self.apply_state(state);
+        self.restore_hnsw()?;
+        self.restore_trained_indexes()
+    }
+
+    fn apply_state(&mut self, state: VFSState) {
+        // El snapshot puede ser anterior a los vectores recuperados del WAL, que siguen en la memtable.
+        let memtable_next_id = self.memtable.keys().max().map(|id| id + 1).unwrap_or(1);
+        self.next_id = state.next_id.max(memtable_next_id);
         self.name = state.name;
         self.index_map = state.index_map;
         self.current_offset = state.current_offset;
         self.quantize = state.quantize;
-        Ok(())
+        self.deleted = state.deleted;
     }
-}
\ No newline at end of file
+
+    // Carga el último snapshot si es coherente con el archivo de datos.
+    // Si no existe, no se puede leer o el archivo de datos ha cambiado desde que se guardó
+    // (por ejemplo, tras un crash), reconstruye el índice recorriendo el archivo de datos.
+    pub fn restore_or_recover(&mut self) -> Result<(), VFSError> {
+        let data_len = data_file_len(&self.paths.data)?;
+        match self.read_state(None) {
+            Ok(state) if state.data_len == data_len => {
+                println!("Estado coherente con el archivo de datos, cargando snapshot");
+                self.apply_state(state);
+                self.restore_hnsw()?;
+                return self.restore_trained_indexes();
+            }
+            Ok(state) => println!("El snapshot es de un archivo de {} bytes, pero el archivo tiene {} bytes", state.data_len, data_len),
+            Err(e) => println!("No se pudo cargar el snapshot: {}", e),
+        }
+
+        self.recover_from_data_file()?;
+        self.restore_hnsw()?;
+        self.restore_trained_indexes()?;
+        // Guardar el índice reconstruido para no repetir la recuperación en el siguiente arranque.
+        self.save_state(None)
+    }
+
+    // Reconstruye el índice, los ids borrados y el siguiente id recorriendo el archivo de datos registro a registro.
+    // Los registros dañados (marca desconocida o checksum incorrecto) se saltan buscando el siguiente registro válido.
+    // Si al final del archivo queda un registro incompleto (escritura interrumpida), se trunca el archivo.
+    // Un archivo con otro formato no se toca: se devuelve `UnsupportedFormat`.
+    pub fn recover_from_data_file(&mut self) -> Result<RecoveryReport, VFSError> {
+        let file_len = match open_data_file(&self.paths.data) {
+            Ok((_, len)) => len,
+            Err(VFSError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => 0,
+            // La cabecera quedó a medias: el archivo no llegó a tener ningún registro.
+            Err(VFSError::CorruptedData(..)) => {
+                println!("Cabecera incompleta en {}, recreando el archivo", self.paths.data);
+                create_data_file(&self.paths.data)?;
+                FILE_HEADER_LEN
+            }
+            Err(e) => return Err(e),
+        };
+        let mut index_map = BTreeMap::new();
+        let mut deleted = BTreeSet::new();
+        let mut max_id = 0;
+        let mut records = 0;
+        let mut tombstones = 0;
+        let mut skipped_bytes = 0;
+
+        let mut last_record_end = FILE_HEADER_LEN;
+        if file_len > FILE_HEADER_LEN {
+            let mut reader = RecordReader::open(&self.paths.data, FILE_HEADER_LEN, None)?;
+            loop {
+                match reader.next_record() {
+                    Ok(Some((offset, record))) => {
+                        skipped_bytes += offset - last_record_end;
+                        match record {
+                            Record::Vector(vector) => {
+                                let id = vector.id();
+                                index_map.insert(id, offset); // La última versión de cada id es la vigente.
+                                deleted.remove(&id);
+                                max_id = max_id.max(id);
+                                records += 1;
+                            }
+                            Record::Tombstone(id) => {
+                                index_map.remove(&id);
+                                deleted.insert(id);
+                                max_id = max_id.max(id);
+                                tombstones += 1;
+                            }
+                        }
+                        last_record_end = reader.offset();
+                    }
+                    Ok(None) => break,
+                    // No empieza un registro válido: avanzar un byte y seguir buscando.
+                    Err(VFSError::CorruptedData(offset, _)) => reader.seek(offset + 1),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        // Todo lo que hay tras el último registro válido es un registro incompleto.
+        let truncated_bytes = file_len.saturating_sub(last_record_end);
+        if truncated_bytes > 0 {
+            println!("Truncando {} bytes incompletos al final de {}", truncated_bytes, self.paths.data);
+            self.invalidate_read_path();
+            OpenOptions::new().write(true).open(&self.paths.data)?.set_len(last_record_end as u64)?;
+        }
+
+        self.index_map = index_map;
+        self.deleted = deleted;
+        self.next_id = self.next_id.max(max_id + 1);
+        self.current_offset = 0;
+
+        let report = RecoveryReport { records, tombstones, skipped_bytes, truncated_bytes, next_id: self.next_id };
+        println!("Índice reconstruido desde el archivo de datos: {:?}", report);
+        Ok(report)
+    }
+
+    // Compacta el archivo de datos de una sola vez, manteniendo el manager bloqueado.
+    // El servidor usa las fases por separado (ver `CompactionPlan`) para liberar el lock mientras se copia.
+    pub fn compact(&mut self) -> Result<CompactionReport, VFSError> {
+        let mut plan = self.begin_compaction()?;
+        if let Err(e) = plan.copy_live_records() {
+            self.abort_compaction(plan);
+            return Err(e);
+        }
+        self.finish_compaction(plan)
+    }
+
+    // Fase 1 de la compactación.
+    pub fn begin_compaction(&mut self) -> Result<CompactionPlan, VFSError> {
+        if self.compacting {
+            return Err(VFSError::CompactionError("A compaction is already running".to_string()));
+        }
+
+        let end = std::fs::metadata(&self.paths.data)?.len() as usize;
+        // Archivo temporal vacío donde se escribirán los registros vivos.
+        create_data_file(&self.paths.compaction)?;
+
+        self.compacting = true;
+        println!("Iniciando compactación de {} ({} bytes)", self.paths.data, end);
+
+        Ok(CompactionPlan {
+            paths: self.paths.clone(),
+            end,
+            index_snapshot: self.index_map.clone(),
+            deleted_snapshot: self.deleted.clone(),
+            offsets: BTreeMap::new(),
+            unindexed: BTreeMap::new(),
+            live_records: 0,
+        })
+    }
+
+    // Cancela una compactación y borra el archivo temporal.
+    pub fn abort_compaction(&mut self, plan: CompactionPlan) {
+        self.compacting = false;
+        if let Err(e) = std::fs::remove_file(&self.paths.compaction) {
+            println!("Advertencia: no se pudo borrar {}: {}", self.paths.compaction, e);
+        }
+        println!("Compactación cancelada tras copiar {} vectores", plan.live_records);
+    }
+
+    // Fase 3 de la compactación.
+    pub fn finish_compaction(&mut self, mut plan: CompactionPlan) -> Result<CompactionReport, VFSError> {
+        match self.swap_compacted_file(&mut plan) {
+            Ok(report) => {
+                self.compacting = false;
+                Ok(report)
+            }
+            Err(e) => {
+                self.abort_compaction(plan);
+                Err(e)
+            }
+        }
+    }
+
+    fn swap_compacted_file(&mut self, plan: &mut CompactionPlan) -> Result<CompactionReport, VFSError> {
+        let bytes_before = std::fs::metadata(&self.paths.data)?.len();
+
+        // Copiar los registros escritos mientras se ejecutaba la fase 2.
+        let mut tail: Vec<(usize, VFSVector)> = Vec::new();
+        scan_records(&self.paths.data, plan.end, bytes_before as usize, |offset, entry| {
+            if self.is_live_record(offset, entry.id()) {
+                tail.push((offset, entry));
+            }
+            Ok(())
+        })?;
+        for (offset, entry) in tail {
+            let new_offset = save_vector(&entry, &self.paths.compaction)?;
+            plan.offsets.insert(offset, new_offset);
+            if !self.index_map.contains_key(&entry.id()) {
+                plan.unindexed.insert(entry.id(), new_offset);
+            }
+            plan.live_records += 1;
+        }
+
+        // Los vectores borrados durante la fase 2 pueden haberse copiado ya, así que necesitan su lápida.
+        for id in self.deleted.difference(&plan.deleted_snapshot) {
+            save_tombstone(*id, &self.paths.compaction)?;
+        }
+        // El archivo compactado debe seguir registrando el mayor id asignado: si ese id está borrado, su lápida
+        // es lo único que queda de él, así que se conserva para que el id no pueda volver a asignarse.
+        if let Some(id) = self.deleted.last().filter(|id| plan.deleted_snapshot.contains(id)) {
+            save_tombstone(*id, &self.paths.compaction)?;
+        }
+
+        File::open(&self.paths.compaction)?.sync_all()?;
+        self.invalidate_read_path(); // La proyección y la caché apuntan al archivo antiguo.
+        std::fs::rename(&self.paths.compaction, &self.paths.data)?;
+        // Los índices en memoria no cambian, pero el grafo guardado cubre offsets del archivo antiguo.
+        self.remove_hnsw_file()?;
+        let bytes_after = std::fs::metadata(&self.paths.data)?.len();
+        // Los demás índices guardan ids, que no cambian al compactar: basta con guardarlos con la nueva longitud.
+        save_trained_index(self.ivf.as_ref(), &self.paths, bytes_after)?;
+        save_trained_index(self.ivf_pq.as_ref(), &self.paths, bytes_after)?;
+        save_trained_index(self.lsh.as_ref(), &self.paths, bytes_after)?;
+        save_trained_index(self.vamana.as_ref(), &self.paths, bytes_after)?;
+
+        // Reconstruir el índice con los nuevos offsets.
+        let mut index_map = BTreeMap::new();
+        for (id, offset) in self.index_map.iter() {
+            match plan.offsets.get(offset) {
+                Some(new_offset) => { index_map.insert(*id, *new_offset); },
+                None => println!("Advertencia: el vector con ID {} no se encontró al compactar", id),
+            }
+        }
+        for (id, new_offset) in plan.unindexed.iter() {
+            if !self.deleted.contains(id) {
+                index_map.entry(*id).or_insert(*new_offset);
+            }
+        }
+        self.index_map = index_map;
+        self.current_offset = 0;
+
+        let reclaimed_bytes = bytes_before.saturating_sub(bytes_after);
+        println!("Compactación completada: {} vectores vivos, {} bytes liberados", plan.live_records, reclaimed_bytes);
+
+        Ok(CompactionReport {
+            live_records: plan.live_records,
+            bytes_before,
+            bytes_after,
+            reclaimed_bytes,
+        })
+    }
+}
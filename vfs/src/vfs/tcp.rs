@@ -4,7 +4,7 @@ use std::net::{TcpListener, TcpStream};
 use std::io::{Read, Write};
 use std::thread;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use serde::{Serialize, Deserialize};
 use serde_json::{Value, json};
 use core::simd::Simd;
@@ -13,7 +13,17 @@ use core::simd::Simd;
 use super::vector::VFSVector;
 use super::err::VFSError;
 use super::storage_manager::{VFSManager, ResetOptions};
-use super::rank::{Ranker, SearchType, DistanceMethod};
+use super::rank::{Ranker, SearchType, DistanceMethod, DEFAULT_DISTANCE_METHOD, MAX_EF_SEARCH, MAX_RERANK, validate_minkowski_p};
+use super::ann::{HnswParams, LshParams, MAX_BUILD_THREADS, default_build_threads, validate_lsh_probes};
+use super::ivf::{IvfParams, validate_nprobe};
+use super::pq::{supports_distance, validate_subquantizers};
+use super::vamana::VamanaParams;
+use super::wal::{Wal, WalHeader, DurabilityMode};
+use super::cache::DEFAULT_CACHE_BYTES;
+use super::collection::{Catalog, CollectionPaths, is_valid_name};
+
+const MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024; // Tamaño máximo de una solicitud HTTP (cabeceras + cuerpo).
+const SEARCH_BATCH_SIZE: usize = 64; // Vectores que se leen del archivo de datos por lote durante una búsqueda.
 
 // Estructuras para las peticiones y respuestas
 #[derive(Deserialize)]
@@ -28,7 +38,95 @@ struct InitRequest {
     vector_dimension: usize,
     storage_name: Option<String>, // opcional
     truncate_data: bool,
-    quantize: bool
+    quantize: bool,
+    durability: Option<String>, // "always" (por defecto), "batched" u "os"
+    wal_sync_every: Option<usize>, // Entradas entre fsyncs en modo "batched"
+    mmap: Option<bool>, // Leer el archivo de datos con mmap (por defecto false)
+    cache_bytes: Option<usize>, // Presupuesto de la caché de lectura en bytes (0 la desactiva)
+    distance_method: Option<String>, // Distancia por defecto de las búsquedas
+    p: Option<f32>, // Exponente de la distancia "minkowski"
+    hnsw: Option<HnswRequest>, // Parámetros del grafo HNSW
+    ivf: Option<IvfRequest>, // Parámetros del índice IVF
+    lsh: Option<LshRequest>, // Parámetros del índice LSH
+    vamana: Option<VamanaRequest>, // Parámetros del grafo Vamana
+}
+
+// Parámetros del grafo HNSW de una colección. Los que falten toman su valor por defecto.
+#[derive(Deserialize, Default)]
+struct HnswRequest {
+    m: Option<usize>,
+    m0: Option<usize>,
+    ef_construction: Option<usize>,
+    level_multiplier: Option<f64>,
+    seed: Option<u64>,
+    heuristic: Option<bool>,
+    keep_pruned: Option<bool>,
+    build_threads: Option<usize>, // No forma parte del grafo: solo afecta a lo rápido que se reconstruye.
+}
+
+// Parámetros del índice IVF de una colección.
+#[derive(Deserialize, Default)]
+struct IvfRequest {
+    nlist: Option<usize>,
+    nprobe: Option<usize>,
+    pq_m: Option<usize>, // Subcuantizadores del índice IVF-PQ
+}
+
+// Parámetros del índice LSH de una colección.
+#[derive(Deserialize, Default)]
+struct LshRequest {
+    tables: Option<usize>,
+    bits: Option<usize>,
+    probes: Option<usize>,
+}
+
+// Parámetros del grafo Vamana de una colección.
+#[derive(Deserialize, Default)]
+struct VamanaRequest {
+    degree: Option<usize>,
+    build_list: Option<usize>,
+    alpha: Option<f32>,
+    pq_m: Option<usize>,
+}
+
+// Petición para crear una colección. Admite las mismas opciones que `/init`.
+#[derive(Deserialize)]
+struct CollectionRequest {
+    name: String,
+    vector_dimension: usize,
+    quantize: Option<bool>,
+    truncate_data: Option<bool>, // Por defecto true: una colección nueva empieza vacía
+    durability: Option<String>,
+    wal_sync_every: Option<usize>,
+    mmap: Option<bool>,
+    cache_bytes: Option<usize>,
+    distance_method: Option<String>,
+    p: Option<f32>,
+    hnsw: Option<HnswRequest>,
+    ivf: Option<IvfRequest>,
+    lsh: Option<LshRequest>,
+    vamana: Option<VamanaRequest>,
+}
+
+impl From<InitRequest> for CollectionRequest {
+    fn from(req: InitRequest) -> Self {
+        CollectionRequest {
+            name: req.storage_name.unwrap_or_else(|| "default_vfs".into()),
+            vector_dimension: req.vector_dimension,
+            quantize: Some(req.quantize),
+            truncate_data: Some(req.truncate_data),
+            durability: req.durability,
+            wal_sync_every: req.wal_sync_every,
+            mmap: req.mmap,
+            cache_bytes: req.cache_bytes,
+            distance_method: req.distance_method,
+            p: req.p,
+            hnsw: req.hnsw,
+            ivf: req.ivf,
+            lsh: req.lsh,
+            vamana: req.vamana,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -36,8 +134,12 @@ struct SearchRequest {
     values: Vec<f32>,
     top_k: usize,
     ef_search: Option<usize>,
+    nprobe: Option<usize>, // Listas que recorre la búsqueda IVF
+    rerank: Option<usize>, // Candidatos IVF-PQ que se reordenan con la distancia exacta
+    probes: Option<usize>, // Cubos vecinos por tabla que visita la búsqueda LSH
     search_type: Option<String>,
     distance_method: Option<String>,
+    p: Option<f32>, // Exponente de la distancia "minkowski". Sin valor se usa el de la colección.
 }
 
 #[derive(Serialize)]
@@ -56,25 +158,171 @@ struct SearchResult {
     vector: Option<VectorResponse>, // Opcional para ahorrar ancho de banda
 }
 
-// Estado del servidor
+// Estado de una colección
 pub struct ServerState {
     manager: VFSManager,
     vector_dimension: usize,
+    distance_method: Option<String>, // Distancia por defecto si la búsqueda no indica ninguna.
+    minkowski_p: Option<f32>,        // Exponente de la distancia por defecto si es "minkowski".
 }
 
-// Función para procesar la solicitud HTTP
-pub fn handle_request(mut stream: TcpStream, state: Arc<Mutex<Option<ServerState>>>) {
-    // Buffer para leer la solicitud
+impl ServerState {
+    // Distancia por defecto de la colección (ya validada al crearla).
+    fn default_distance(&self) -> Option<DistanceMethod> {
+        self.distance_method.as_deref().and_then(|method| parse_distance_method(method, self.minkowski_p).ok())
+    }
+}
+
+// Una colección con distancia Hamming o Jaccard por defecto solo guarda vectores binarios o no negativos.
+fn validate_for_collection(state: &ServerState, values: &[f32]) -> Result<(), String> {
+    state.default_distance().map_or(Ok(()), |distance_method| distance_method.validate_values(values))
+}
+
+// Cada colección tiene su propio lock, así que las peticiones a colecciones distintas no se bloquean entre sí.
+type CollectionSlot = Arc<Mutex<Option<ServerState>>>;
+
+// Colecciones del servidor.
+// El lock del registro solo se toma para buscar, crear o borrar colecciones, nunca mientras se atiende una petición.
+pub struct Collections {
+    slots: BTreeMap<String, CollectionSlot>,
+    default: Option<String>, // Colección creada con `/init`, que atienden las rutas sin `/collections/<nombre>`.
+}
+
+impl Collections {
+    fn get(&self, name: &str) -> Option<CollectionSlot> {
+        self.slots.get(name).cloned()
+    }
+
+    fn save_catalog(&self) -> Result<(), VFSError> {
+        Catalog {
+            collections: self.slots.keys().cloned().collect(),
+            default: self.default.clone(),
+        }.save()
+    }
+}
+
+// Recupera todas las colecciones del catálogo. Se llama al arrancar, antes de aceptar conexiones.
+pub fn recover_collections() -> Collections {
+    let catalog = Catalog::load().unwrap_or_else(|e| {
+        eprintln!("No se pudo leer el catálogo de colecciones: {}", e);
+        Catalog::default()
+    });
+
+    let mut slots = BTreeMap::new();
+    for name in catalog.collections {
+        match recover_state(&name) {
+            Some(state) => {
+                println!("Colección '{}' recuperada desde el WAL", name);
+                slots.insert(name, Arc::new(Mutex::new(Some(state))));
+            }
+            None => eprintln!("No se pudo recuperar la colección '{}'", name),
+        }
+    }
+
+    let default = catalog.default.filter(|name| slots.contains_key(name));
+    Collections { slots, default }
+}
+
+// Reconstruye el estado de una colección a partir de su WAL, si existe.
+// Primero se restaura el índice (desde el snapshot o, si no es coherente con el archivo de datos,
+// recorriendo el archivo) y después se reproduce el WAL para recuperar los vectores que seguían en la memtable.
+pub fn recover_state(name: &str) -> Option<ServerState> {
+    let wal_path = CollectionPaths::new(name).wal;
+    let (header, entries) = match Wal::read(&wal_path) {
+        Ok(Some(log)) => log,
+        Ok(None) => return None,
+        Err(e) => {
+            eprintln!("No se pudo leer el WAL: {}", e);
+            return None;
+        }
+    };
+
+    let mut manager = VFSManager::new(&header.name, Some(header.quantize));
+    manager.set_mmap(header.mmap);
+    manager.set_cache_bytes(header.cache_bytes);
+    let index_distance = header.distance_method.as_deref()
+        .and_then(|method| parse_distance_method(method, header.minkowski_p).ok())
+        .unwrap_or(DEFAULT_DISTANCE_METHOD);
+    manager.set_index_config(index_distance, header.hnsw);
+    manager.set_index_threads(header.hnsw_threads);
+    manager.set_ivf_params(header.ivf);
+    manager.set_lsh_params(header.lsh);
+    manager.set_vamana_params(header.vamana);
+    if let Err(e) = manager.restore_or_recover() {
+        eprintln!("No se pudo reconstruir el índice: {}", e);
+    }
+    manager.replay_wal(entries);
+
+    match Wal::open(&wal_path, header.clone()) {
+        Ok(wal) => manager.attach_wal(wal),
+        Err(e) => {
+            eprintln!("No se pudo abrir el WAL: {}", e);
+            return None;
+        }
+    }
+
+    Some(ServerState {
+        manager,
+        vector_dimension: header.vector_dimension,
+        distance_method: header.distance_method,
+        minkowski_p: header.minkowski_p,
+    })
+}
+
+// Lee una solicitud HTTP completa: las cabeceras y después todo el cuerpo que indique Content-Length.
+// Una sola lectura no basta, porque los vectores de dimensión alta no caben en un paquete.
+// Devuelve None si la conexión se cierra sin datos o la solicitud supera `MAX_REQUEST_BYTES`.
+fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request = Vec::new();
     let mut buffer = [0; 4096];
-    
-    // Leer la solicitud
-    let bytes_read = stream.read(&mut buffer).unwrap_or(0);
-    if bytes_read == 0 {
-        return;
+    let mut expected_len = None; // Tamaño total (cabeceras + cuerpo), conocido al terminar las cabeceras.
+
+    loop {
+        let bytes_read = stream.read(&mut buffer).unwrap_or(0);
+        if bytes_read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..bytes_read]);
+        if request.len() > MAX_REQUEST_BYTES {
+            return None;
+        }
+
+        if expected_len.is_none() {
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                let content_len = headers.lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                // curl espera esta respuesta antes de enviar los cuerpos grandes.
+                if headers.lines().any(|line| line.starts_with("expect:") && line.contains("100-continue")) {
+                    let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+                }
+                expected_len = Some(end + 4 + content_len);
+            }
+        }
+        if expected_len.is_some_and(|len| request.len() >= len) {
+            break;
+        }
     }
+
+    if request.is_empty() {
+        None
+    } else {
+        Some(request)
+    }
+}
+
+// Función para procesar la solicitud HTTP
+pub fn handle_request(mut stream: TcpStream, collections: Arc<Mutex<Collections>>) {
+    // Leer la solicitud
+    let request = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
     
     // Convertir los bytes a string
-    let request_str = String::from_utf8_lossy(&buffer[..bytes_read]);
+    let request_str = String::from_utf8_lossy(&request);
     
     // Parsear la solicitud HTTP
     let request_lines: Vec<&str> = request_str.lines().collect();
@@ -104,68 +352,166 @@ pub fn handle_request(mut stream: TcpStream, state: Arc<Mutex<Option<ServerState
     }
     
     // Procesar la solicitud
-    let (status, response_body) = match (method, path) {
-        ("GET", p) if p.starts_with("/vectors/") => {
-            let id_str = p.trim_start_matches("/vectors/");
-            match id_str.parse::<u64>() {
-                Ok(id) => get_vector(id, &state),
-                Err(_) => (400, json!({"error": "Invalid vector ID"}).to_string()),
-            }
-        },
+    let (status, response_body) = route(method, path, &body, &collections);
+    
+    // Construir la respuesta HTTP
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status_code_to_text(status),
+        response_body.len(),
+        response_body
+    );
+    
+    // Enviar la respuesta
+    let _ = stream.write(response.as_bytes());
+    let _ = stream.flush();
+}
+
+
+// Decide qué colección atiende la petición.
+// - `/init` y `/collections` gestionan las colecciones.
+// - `/collections/<nombre>/...` se dirige a esa colección.
+// - El resto de rutas se dirigen a la colección por defecto.
+fn route(method: &str, path: &str, body: &str, collections: &Arc<Mutex<Collections>>) -> (u16, String) {
+    match (method, path) {
         ("POST", "/init") => {
           
-            if let Ok(init_request) = serde_json::from_str::<InitRequest>(&body) {
+            if let Ok(init_request) = serde_json::from_str::<InitRequest>(body) {
 
                     println!("Solictud correcta");
-                    init_manager(init_request, &state)
+                    init_default_collection(init_request, collections)
                 } else {
                     (400, json!({"error": "Invalid JSON for init"}).to_string())
                 }
             
         }
+        ("POST", "/collections") => {
+            if let Ok(request) = serde_json::from_str::<CollectionRequest>(body) {
+                create_collection(request, collections)
+            } else {
+                (400, json!({"error": "Invalid JSON for collection"}).to_string())
+            }
+        },
+        ("GET", "/collections") => {
+            list_collections(collections)
+        },
+        (_, p) if p.starts_with("/collections/") => {
+            let scoped = p.trim_start_matches("/collections/");
+            let (name, rest) = match scoped.find('/') {
+                Some(i) => (&scoped[..i], &scoped[i..]),
+                None => (scoped, ""),
+            };
+
+            if method == "DELETE" && rest.is_empty() {
+                return drop_collection(name, collections);
+            }
+
+            let slot = collections.lock().unwrap().get(name);
+            match slot {
+                Some(slot) => collection_request(method, rest, body, &slot),
+                None => (404, json!({"error": format!("Collection not found: {}", name)}).to_string()),
+            }
+        },
+        _ => {
+            // Sin colección por defecto los handlers responden que el manager no está inicializado.
+            let slot = {
+                let registry = collections.lock().unwrap();
+                registry.default.as_deref().and_then(|name| registry.get(name))
+            };
+            let slot = slot.unwrap_or_else(|| Arc::new(Mutex::new(None)));
+            collection_request(method, path, body, &slot)
+        }
+    }
+}
+
+// Rutas de una colección.
+fn collection_request(method: &str, path: &str, body: &str, state: &CollectionSlot) -> (u16, String) {
+    match (method, path) {
+        ("GET", p) if p.starts_with("/vectors/") => {
+            let id_str = p.trim_start_matches("/vectors/");
+            match id_str.parse::<u64>() {
+                Ok(id) => get_vector(id, state),
+                Err(_) => (400, json!({"error": "Invalid vector ID"}).to_string()),
+            }
+        },
+        ("PUT", p) if p.starts_with("/vectors/") => {
+            let id_str = p.trim_start_matches("/vectors/");
+            match (id_str.parse::<u64>(), serde_json::from_str::<VectorRegisterRequest>(body)) {
+                (Ok(id), Ok(request)) => upsert_vector(id, request, state),
+                (Err(_), _) => (400, json!({"error": "Invalid vector ID"}).to_string()),
+                (_, Err(_)) => (400, json!({"error": "Invalid JSON request"}).to_string()),
+            }
+        },
+        ("DELETE", p) if p.starts_with("/vectors/") => {
+            let id_str = p.trim_start_matches("/vectors/");
+            match id_str.parse::<u64>() {
+                Ok(id) => delete_vector(id, state),
+                Err(_) => (400, json!({"error": "Invalid vector ID"}).to_string()),
+            }
+        },
         ("POST", "/vectors") => {
-            if let Ok(request) = serde_json::from_str::<VectorRegisterRequest>(&body) {
-                register_vector(request, &state)
+            if let Ok(request) = serde_json::from_str::<VectorRegisterRequest>(body) {
+                register_vector(request, state)
             } else {
                 (400, json!({"error": "Invalid JSON request"}).to_string())
             }
         },
         ("POST", "/search") => {
-            if let Ok(request) = serde_json::from_str::<SearchRequest>(&body) {
-                search(request, &state)
+            if let Ok(request) = serde_json::from_str::<SearchRequest>(body) {
+                search(request, state)
             } else {
                 (400, json!({"error": "Invalid JSON request"}).to_string())
             }
         },
         ("POST", "/flush") => {
-            flush_memtable(&state)
+            flush_memtable(state)
         },
         ("POST", "/snapshot") => {
-            save_state(&state)
+            save_state(state)
         }
         ("POST", "/restore") => {
-            load_state(&state)
+            load_state(state)
+        }
+        ("POST", "/compact") => {
+            compact(state)
+        }
+        ("GET", "/stats") => {
+            get_stats(state)
         }
         _ => (404, json!({"error": "Not found"}).to_string()),
-    };
-    
-    // Construir la respuesta HTTP
-    let response = format!(
-        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        status_code_to_text(status),
-        response_body.len(),
-        response_body
-    );
-    
-    // Enviar la respuesta
-    let _ = stream.write(response.as_bytes());
-    let _ = stream.flush();
+    }
 }
 
 
 
 
 // Funciones de utilidad
+// `p` solo se usa con "minkowski", que lo necesita.
+// Los nombres sin prefijo usan la versión SIMD; la escalar se pide con el prefijo "scalar_".
+fn parse_distance_method(method: &str, p: Option<f32>) -> Result<DistanceMethod, String> {
+    match method {
+        "cosine" | "simd_cosine" => Ok(DistanceMethod::SimdCosine),
+        "scalar_cosine" => Ok(DistanceMethod::Cosine),
+        "euclidean" | "simd_euclidean" => Ok(DistanceMethod::SimdEuclidean),
+        "scalar_euclidean" => Ok(DistanceMethod::Euclidean),
+        "dot" | "simd_dot" => Ok(DistanceMethod::SimdDotProduct),
+        "scalar_dot" => Ok(DistanceMethod::DotProduct),
+        "manhattan" | "simd_manhattan" => Ok(DistanceMethod::SimdManhattan),
+        "scalar_manhattan" => Ok(DistanceMethod::Manhattan),
+        "chebyshev" | "simd_chebyshev" => Ok(DistanceMethod::SimdChebyshev),
+        "scalar_chebyshev" => Ok(DistanceMethod::Chebyshev),
+        "minkowski" => match p {
+            Some(p) => validate_minkowski_p(p).map(|_| DistanceMethod::Minkowski(p)),
+            None => Err("Minkowski distance requires p".to_string()),
+        },
+        "hamming" | "simd_hamming" => Ok(DistanceMethod::SimdHamming),
+        "scalar_hamming" => Ok(DistanceMethod::Hamming),
+        "jaccard" | "simd_jaccard" => Ok(DistanceMethod::SimdJaccard),
+        "scalar_jaccard" => Ok(DistanceMethod::Jaccard),
+        _ => Err(format!("Invalid distance method: {}", method)),
+    }
+}
+
 fn status_code_to_text(status: u16) -> &'static str {
     match status {
         200 => "200 OK",
@@ -173,6 +519,7 @@ fn status_code_to_text(status: u16) -> &'static str {
         204 => "204 No Content",
         400 => "400 Bad Request",
         404 => "404 Not Found",
+        409 => "409 Conflict",
         500 => "500 Internal Server Error",
         _ => "200 OK",
     }
@@ -211,6 +558,44 @@ fn load_state(state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
 }
 
 
+// La compactación suelta el lock del servidor mientras copia los registros,
+// así que el resto de peticiones pueden seguir atendiéndose durante la mayor parte del proceso.
+fn compact(state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
+    // Fase 1: foto del estado con el lock tomado.
+    let mut plan = {
+        let mut guard = state.lock().unwrap();
+        match guard.as_mut() {
+            Some(inner_state) => match inner_state.manager.begin_compaction() {
+                Ok(plan) => plan,
+                Err(e @ VFSError::CompactionError(_)) => return (409, json!({"error": e.to_string()}).to_string()),
+                Err(e) => return (500, json!({"error": format!("Failed to compact: {}", e)}).to_string()),
+            },
+            None => return (400, json!({"error": "VFSManager is not initialized"}).to_string()),
+        }
+    };
+
+    // Fase 2: copia de los registros vivos sin el lock.
+    let copy_result = plan.copy_live_records();
+
+    // Fase 3: sustitución del archivo de datos con el lock tomado.
+    let mut guard = state.lock().unwrap();
+    let inner_state = match guard.as_mut() {
+        Some(inner_state) => inner_state,
+        None => return (400, json!({"error": "VFSManager is not initialized"}).to_string()),
+    };
+
+    if let Err(e) = copy_result {
+        inner_state.manager.abort_compaction(plan);
+        return (500, json!({"error": format!("Failed to compact: {}", e)}).to_string());
+    }
+
+    match inner_state.manager.finish_compaction(plan) {
+        Ok(report) => (200, json!({"status": "success", "report": report}).to_string()),
+        Err(e) => (500, json!({"error": format!("Failed to compact: {}", e)}).to_string()),
+    }
+}
+
+
 fn flush_memtable(state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
     let mut guard = state.lock().unwrap();
 
@@ -239,6 +624,27 @@ fn flush_memtable(state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
 }
 
 
+fn get_stats(state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
+    let guard = state.lock().unwrap();
+
+    if let Some(inner_state) = guard.as_ref() {
+        let manager = &inner_state.manager;
+        (200, json!({
+            "memtable_size": manager.get_memtable_size(),
+            "total_vectors": manager.get_total_vectors_estimate(),
+            "cache": manager.cache_stats(),
+            "hnsw": manager.index_stats(),
+            "ivf": manager.ivf_stats(),
+            "ivf_pq": manager.ivf_pq_stats(),
+            "lsh": manager.lsh_stats(),
+            "vamana": manager.vamana_stats(),
+        }).to_string())
+    } else {
+        (400, json!({"error": "VFSManager is not initialized"}).to_string())
+    }
+}
+
+
 fn get_vector(id: u64, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
     let mut state_guard = state.lock().unwrap();
 
@@ -267,17 +673,143 @@ fn get_vector(id: u64, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String)
     }
 }
 
-fn init_manager(req: InitRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
+fn upsert_vector(id: u64, req: VectorRegisterRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
+    let mut state_guard = state.lock().unwrap();
+
+    if let Some(inner_state) = state_guard.as_mut() {
+        // Validar dimensión del vector
+        if req.values.len() != inner_state.vector_dimension {
+            return (400, json!({
+                "error": format!("Vector dimension mismatch. Expected {}, got {}",
+                                inner_state.vector_dimension, req.values.len())
+            }).to_string());
+        }
+        if let Err(e) = validate_for_collection(inner_state, &req.values) {
+            return (400, json!({"error": e}).to_string());
+        }
+
+        match inner_state.manager.upsert_vector(id, req.values, &req.name, req.tags) {
+            Ok(id) => (200, json!({"id": id, "status": "success"}).to_string()),
+            Err(VFSError::InvalidVector(msg)) => (400, json!({"error": msg}).to_string()),
+            Err(VFSError::NotFound(_)) => (404, json!({"error": "Vector not found"}).to_string()),
+            Err(e) => (500, json!({"error": format!("Failed to upsert vector: {}", e)}).to_string()),
+        }
+    } else {
+        (
+            400,
+            json!({
+                "error": "VFSManager is not initialized"
+            })
+            .to_string(),
+        )
+    }
+}
+
+fn delete_vector(id: u64, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
+    let mut state_guard = state.lock().unwrap();
+
+    if let Some(inner_state) = state_guard.as_mut() {
+        match inner_state.manager.delete_vector(id) {
+            Ok(_) => (200, json!({"id": id, "status": "deleted"}).to_string()),
+            Err(VFSError::NotFound(_)) => (404, json!({"error": "Vector not found"}).to_string()),
+            Err(e) => (500, json!({"error": format!("Failed to delete vector: {}", e)}).to_string()),
+        }
+    } else {
+        (
+            400,
+            json!({
+                "error": "VFSManager is not initialized"
+            })
+            .to_string(),
+        )
+    }
+}
+
+fn init_manager(req: CollectionRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
     let mut guard = state.lock().unwrap();
     println!("He obtenido el lock");
     if guard.is_some() {
         return (400, json!({"error": "VFSManager is already initialized"}).to_string());
     }
 
-    let mut manager = VFSManager::new(&req.storage_name.unwrap_or_else(|| "default_vfs".into()),  Some(req.quantize));
+    let durability = match req.durability.as_deref() {
+        None => DurabilityMode::Always,
+        Some(mode) => match DurabilityMode::parse(mode, req.wal_sync_every) {
+            Some(durability) => durability,
+            None => return (400, json!({"error": format!("Invalid durability mode: {}", mode)}).to_string()),
+        },
+    };
+
+    let index_distance = match req.distance_method.as_deref() {
+        None => DEFAULT_DISTANCE_METHOD,
+        Some(method) => match parse_distance_method(method, req.p) {
+            Ok(distance_method) => distance_method,
+            Err(e) => return (400, json!({"error": e}).to_string()),
+        },
+    };
+
+    let hnsw = req.hnsw.unwrap_or_default();
+    let hnsw_params = match HnswParams::new(
+        hnsw.m,
+        hnsw.m0,
+        hnsw.ef_construction,
+        hnsw.level_multiplier,
+        hnsw.seed,
+        hnsw.heuristic,
+        hnsw.keep_pruned,
+    ) {
+        Ok(params) => params,
+        Err(e) => return (400, json!({"error": format!("Invalid HNSW parameters: {}", e)}).to_string()),
+    };
+    let hnsw_threads = hnsw.build_threads.unwrap_or_else(default_build_threads);
+    if hnsw_threads == 0 || hnsw_threads > MAX_BUILD_THREADS {
+        return (400, json!({"error": format!("Invalid HNSW parameters: build_threads must be between 1 and {}", MAX_BUILD_THREADS)}).to_string());
+    }
+
+    let ivf = req.ivf.unwrap_or_default();
+    let ivf_params = match IvfParams::new(ivf.nlist, ivf.nprobe, ivf.pq_m) {
+        Ok(params) => params,
+        Err(e) => return (400, json!({"error": format!("Invalid IVF parameters: {}", e)}).to_string()),
+    };
+    if let Some(pq_m) = ivf_params.pq_m {
+        if let Err(e) = validate_subquantizers(pq_m, req.vector_dimension) {
+            return (400, json!({"error": format!("Invalid IVF parameters: {}", e)}).to_string());
+        }
+    }
+
+    let lsh = req.lsh.unwrap_or_default();
+    let lsh_params = match LshParams::new(lsh.tables, lsh.bits, lsh.probes) {
+        Ok(params) => params,
+        Err(e) => return (400, json!({"error": format!("Invalid LSH parameters: {}", e)}).to_string()),
+    };
+
+    let vamana = req.vamana.unwrap_or_default();
+    let vamana_params = match VamanaParams::new(vamana.degree, vamana.build_list, vamana.alpha, vamana.pq_m) {
+        Ok(params) => params,
+        Err(e) => return (400, json!({"error": format!("Invalid Vamana parameters: {}", e)}).to_string()),
+    };
+    if let Some(pq_m) = vamana_params.pq_m {
+        if let Err(e) = validate_subquantizers(pq_m, req.vector_dimension) {
+            return (400, json!({"error": format!("Invalid Vamana parameters: {}", e)}).to_string());
+        }
+    }
+
+    let name = req.name;
+    let quantize = req.quantize.unwrap_or(false);
+    let truncate_data = req.truncate_data.unwrap_or(true);
+    let mut manager = VFSManager::new(&name,  Some(quantize));
+    let mmap = req.mmap.unwrap_or(false);
+    manager.set_mmap(mmap);
+    let cache_bytes = req.cache_bytes.unwrap_or(DEFAULT_CACHE_BYTES);
+    manager.set_cache_bytes(cache_bytes);
+    manager.set_index_config(index_distance, hnsw_params);
+    manager.set_index_threads(hnsw_threads);
+    manager.set_ivf_params(ivf_params);
+    manager.set_lsh_params(lsh_params);
+    manager.set_vamana_params(vamana_params);
 
     let reset_options = ResetOptions {
-        truncate_data_file: req.truncate_data,
+        truncate_data_file: truncate_data,
         storage_path: None,
         reset_offset: true,
         new_offset: Some(0),
@@ -288,16 +820,148 @@ fn init_manager(req: InitRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u
         reset_quantize: false
     };
 
-    manager.reset_state(reset_options);
+    if let Err(e) = manager.reset_state(reset_options) {
+        return (500, json!({"error": format!("Failed to reset state: {}", e)}).to_string());
+    }
+
+    // Si se conserva el archivo de datos, recuperar su índice y el contador de ids.
+    if !truncate_data {
+        if let Err(e) = manager.restore_or_recover() {
+            return (500, json!({"error": format!("Failed to recover existing data: {}", e)}).to_string());
+        }
+    }
+
+    // Empezar un WAL nuevo para este manager.
+    let header = WalHeader {
+        name,
+        vector_dimension: req.vector_dimension,
+        quantize,
+        durability,
+        mmap,
+        cache_bytes,
+        distance_method: req.distance_method.clone(),
+        minkowski_p: req.p,
+        hnsw: hnsw_params,
+        hnsw_threads,
+        ivf: ivf_params,
+        lsh: lsh_params,
+        vamana: vamana_params,
+    };
+    let wal_path = manager.paths().wal.clone();
+    match Wal::create(&wal_path, header) {
+        Ok(wal) => manager.attach_wal(wal),
+        Err(e) => return (500, json!({"error": format!("Failed to create WAL: {}", e)}).to_string()),
+    }
 
     *guard = Some(ServerState {
         manager,
         vector_dimension: req.vector_dimension,
+        distance_method: req.distance_method,
+        minkowski_p: req.p,
     });
 
     (200, json!({"status": "initialized"}).to_string())
 }
 
+// `/init` crea la colección por defecto.
+fn init_default_collection(req: InitRequest, collections: &Arc<Mutex<Collections>>) -> (u16, String) {
+    let mut registry = collections.lock().unwrap();
+    let req = CollectionRequest::from(req);
+    if !is_valid_name(&req.name) {
+        return (400, json!({"error": format!("Invalid collection name: {}", req.name)}).to_string());
+    }
+    if registry.default.is_some() || registry.slots.contains_key(&req.name) {
+        return (400, json!({"error": "VFSManager is already initialized"}).to_string());
+    }
+
+    let name = req.name.clone();
+    let slot: CollectionSlot = Arc::new(Mutex::new(None));
+    let (status, body) = init_manager(req, &slot);
+    if status != 200 {
+        return (status, body);
+    }
+
+    registry.slots.insert(name.clone(), slot);
+    registry.default = Some(name);
+    if let Err(e) = registry.save_catalog() {
+        return (500, json!({"error": format!("Failed to save collection catalog: {}", e)}).to_string());
+    }
+    (status, body)
+}
+
+fn create_collection(req: CollectionRequest, collections: &Arc<Mutex<Collections>>) -> (u16, String) {
+    let mut registry = collections.lock().unwrap();
+    if !is_valid_name(&req.name) {
+        return (400, json!({"error": format!("Invalid collection name: {}", req.name)}).to_string());
+    }
+    if registry.slots.contains_key(&req.name) {
+        return (409, json!({"error": format!("Collection already exists: {}", req.name)}).to_string());
+    }
+
+    let name = req.name.clone();
+    let slot: CollectionSlot = Arc::new(Mutex::new(None));
+    let (status, body) = init_manager(req, &slot);
+    if status != 200 {
+        return (status, body);
+    }
+
+    registry.slots.insert(name.clone(), slot);
+    if let Err(e) = registry.save_catalog() {
+        return (500, json!({"error": format!("Failed to save collection catalog: {}", e)}).to_string());
+    }
+    (201, json!({"name": name, "status": "created"}).to_string())
+}
+
+fn list_collections(collections: &Arc<Mutex<Collections>>) -> (u16, String) {
+    let registry = collections.lock().unwrap();
+    let list: Vec<Value> = registry.slots.iter()
+        .filter_map(|(name, slot)| {
+            let guard = slot.lock().unwrap();
+            let inner_state = guard.as_ref()?;
+            Some(json!({
+                "name": name,
+                "vector_dimension": inner_state.vector_dimension,
+                "quantize": inner_state.manager.is_quantized(),
+                "distance_method": inner_state.distance_method,
+                "p": inner_state.minkowski_p,
+                "hnsw": inner_state.manager.index_params(),
+                "ivf": inner_state.manager.ivf_params(),
+                "lsh": inner_state.manager.lsh_params(),
+                "vamana": inner_state.manager.vamana_params(),
+                "total_vectors": inner_state.manager.get_total_vectors_estimate(),
+                "default": registry.default.as_deref() == Some(name.as_str()),
+            }))
+        })
+        .collect();
+
+    (200, json!({"collections": list}).to_string())
+}
+
+// Borra una colección y todos sus archivos.
+fn drop_collection(name: &str, collections: &Arc<Mutex<Collections>>) -> (u16, String) {
+    let mut registry = collections.lock().unwrap();
+    let slot = match registry.slots.remove(name) {
+        Some(slot) => slot,
+        None => return (404, json!({"error": format!("Collection not found: {}", name)}).to_string()),
+    };
+    if registry.default.as_deref() == Some(name) {
+        registry.default = None;
+    }
+    if let Err(e) = registry.save_catalog() {
+        return (500, json!({"error": format!("Failed to save collection catalog: {}", e)}).to_string());
+    }
+
+    // Las peticiones en curso que ya tenían la colección verán que no está inicializada.
+    let paths = match slot.lock().unwrap().take() {
+        Some(inner_state) => inner_state.manager.paths().clone(),
+        None => CollectionPaths::new(name),
+    };
+    match paths.remove_files() {
+        Ok(_) => (200, json!({"name": name, "status": "deleted"}).to_string()),
+        Err(e) => (500, json!({"error": format!("Failed to delete collection files: {}", e)}).to_string()),
+    }
+}
+
 fn register_vector(req: VectorRegisterRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u16, String) {
     let mut state_guard = state.lock().unwrap();
 
@@ -310,6 +974,9 @@ fn register_vector(req: VectorRegisterRequest, state: &Arc<Mutex<Option<ServerSt
                             inner_state.vector_dimension, req.values.len())
         }).to_string());
     }
+    if let Err(e) = validate_for_collection(inner_state, &req.values) {
+        return (400, json!({"error": e}).to_string());
+    }
     
     // Crear un vector F32 a partir de los valores
     // Intentar registrar el vector usando la nueva función que devuelve Result
@@ -382,25 +1049,57 @@ fn search(req: SearchRequest, state: &Arc<Mutex<Option<ServerState>>>) -> (u16,
     // Configurar la búsqueda
     let search_type = match req.search_type.as_deref() {
         Some("exact") => SearchType::Exact,
-        _ => SearchType::Approximate,
+        Some("ivf") => SearchType::Ivf,
+        Some("ivf_pq") => SearchType::IvfPq,
+        Some("lsh") => SearchType::Lsh,
+        Some("vamana") => SearchType::Vamana,
+        None | Some("approximate") => SearchType::Approximate,
+        Some(other) => return (400, json!({"error": format!("Invalid search type: {}", other)}).to_string()),
     };
     
-    let distance_method = match req.distance_method.as_deref() {
-        Some("cosine") => DistanceMethod::Cosine,
-        Some("euclidean") => DistanceMethod::Euclidean,
-        Some("simd_cosine") => DistanceMethod::SimdCosine,
-        Some("simd_euclidean") => DistanceMethod::SimdEuclidean,
-        _ => DistanceMethod::Euclidean,
+    // Si la petición no indica distancia se usa la de la colección.
+    let distance_method = match req.distance_method.as_deref().or(inner_state.distance_method.as_deref()) {
+        None => DEFAULT_DISTANCE_METHOD,
+        Some(method) => match parse_distance_method(method, req.p.or(inner_state.minkowski_p)) {
+            Ok(distance_method) => distance_method,
+            Err(e) => return (400, json!({"error": e}).to_string()),
+        },
     };
+    if let Err(e) = distance_method.validate_values(&query_vector.as_f32_vec()) {
+        return (400, json!({"error": e}).to_string());
+    }
     
     // Ejecutar búsqueda
-    let ef_search = req.ef_search.unwrap_or(6);
-    let mut ranker = Ranker::new(search_type, distance_method, Some(ef_search));
+    if let Some(ef_search) = req.ef_search {
+        if ef_search == 0 || ef_search > MAX_EF_SEARCH {
+            return (400, json!({"error": format!("ef_search must be between 1 and {}", MAX_EF_SEARCH)}).to_string());
+        }
+    }
+    if let Some(nprobe) = req.nprobe {
+        if let Err(e) = validate_nprobe(nprobe) {
+            return (400, json!({"error": e}).to_string());
+        }
+    }
+    if matches!(req.rerank, Some(rerank) if rerank > MAX_RERANK) {
+        return (400, json!({"error": format!("rerank must be at most {}", MAX_RERANK)}).to_string());
+    }
+    if let Some(probes) = req.probes {
+        if let Err(e) = validate_lsh_probes(probes) {
+            return (400, json!({"error": e}).to_string());
+        }
+    }
+    if matches!(search_type, SearchType::Lsh) && !matches!(distance_method, DistanceMethod::Cosine | DistanceMethod::SimdCosine) {
+        return (400, json!({"error": "LSH search only supports cosine distance"}).to_string());
+    }
+    if matches!(search_type, SearchType::IvfPq) && !supports_distance(&distance_method) {
+        return (400, json!({"error": "IVF-PQ search does not support Chebyshev or Jaccard distance"}).to_string());
+    }
+    let mut ranker = Ranker::new(search_type, distance_method, req.ef_search, req.nprobe, req.rerank, req.probes);
     
     // Medir tiempo de consulta
     let start = std::time::Instant::now();
     
-    match ranker.search(&query_vector, req.top_k, Some(ef_search), &mut inner_state.manager) {
+    match ranker.search(&query_vector, SEARCH_BATCH_SIZE, Some(req.top_k), &mut inner_state.manager) {
         Ok(search_results) => {
             let query_time = start.elapsed();
             
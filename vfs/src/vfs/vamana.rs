@@ -0,0 +1,441 @@
+// Índice Vamana en disco (estilo DiskANN).
+//
+// Vamana es un grafo de una sola capa en el que cada nodo tiene como mucho `degree` vecinos. Se construye en dos pasadas:
+// para cada nodo se hace una búsqueda voraz desde el medoide y se eligen sus vecinos entre los nodos visitados con
+// "robust prune": un candidato se descarta si ya hay un vecino elegido `alpha` veces más cerca de él que el propio nodo.
+// Con alpha > 1 (segunda pasada) se conservan enlaces largos, así que las búsquedas llegan en pocos saltos.
+//
+// El grafo no se guarda en memoria. Cada nodo es un registro de tamaño fijo (id, vecinos y vector completo) en un
+// archivo dividido en bloques de 4 KB, sin que ningún registro cruce un bloque si cabe en uno. En memoria solo quedan
+// los códigos PQ de los vectores: la búsqueda usa sus distancias aproximadas para decidir qué nodo expandir, lee del
+// disco el registro de cada nodo expandido y, con el vector completo del registro, calcula su distancia exacta.
+// El resultado son los nodos expandidos más cercanos según esa distancia exacta. Con el producto escalar el grafo
+// se construye igualmente con la distancia euclídea y solo la búsqueda usa el producto escalar.
+//
+// El archivo del grafo no cambia después de construirlo. Los vectores insertados o actualizados después se guardan en
+// memoria y se comparan uno a uno en cada búsqueda; sus nodos antiguos y los de los borrados se siguen recorriendo,
+// pero no se devuelven. Cuando esos cambios pasan de una fracción del grafo, el manager lo vuelve a construir.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+use super::vector::{VFSVector, VectorView};
+use super::rank::{DistanceMethod, view_distance};
+use super::ivf::slice_distance;
+use super::pq::{PqCodec, default_subquantizers, normalized, prepare};
+
+pub const DEFAULT_VAMANA_DEGREE: usize = 32;
+pub const DEFAULT_VAMANA_BUILD_LIST: usize = 100;
+pub const DEFAULT_VAMANA_ALPHA: f32 = 1.2;
+pub const MAX_VAMANA_DEGREE: usize = 256;
+pub const MAX_VAMANA_BUILD_LIST: usize = 10_000;
+/// Fracción de vectores cambiados desde la construcción a partir de la cual hay que reconstruir el grafo.
+pub const MAX_OUTDATED_FRACTION: f64 = 0.2;
+const BLOCK_SIZE: usize = 4096;
+const GRAPH_MAGIC: &[u8; 8] = b"VFSVAMN1";
+const VAMANA_SEED: u64 = 0xd15c;
+const NO_NEIGHBOR: u32 = u32::MAX; // Relleno de las posiciones libres de la lista de vecinos.
+
+// Parámetros de construcción del grafo Vamana de una colección.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VamanaParams {
+    pub degree: usize,       // Vecinos máximos por nodo (R).
+    pub build_list: usize,   // Tamaño de la lista de candidatos al construir (L).
+    pub alpha: f32,          // Factor de la segunda pasada de robust prune.
+    pub pq_m: Option<usize>, // Subcuantizadores de los códigos en memoria. Sin valor se eligen según la dimensión.
+}
+
+impl Default for VamanaParams {
+    fn default() -> Self {
+        Self {
+            degree: DEFAULT_VAMANA_DEGREE,
+            build_list: DEFAULT_VAMANA_BUILD_LIST,
+            alpha: DEFAULT_VAMANA_ALPHA,
+            pq_m: None,
+        }
+    }
+}
+
+impl VamanaParams {
+    pub fn new(degree: Option<usize>, build_list: Option<usize>, alpha: Option<f32>, pq_m: Option<usize>) -> Result<Self, String> {
+        let params = Self {
+            degree: degree.unwrap_or(DEFAULT_VAMANA_DEGREE),
+            build_list: build_list.unwrap_or(DEFAULT_VAMANA_BUILD_LIST),
+            alpha: alpha.unwrap_or(DEFAULT_VAMANA_ALPHA),
+            pq_m,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.degree == 0 || self.degree > MAX_VAMANA_DEGREE {
+            return Err(format!("degree must be between 1 and {}", MAX_VAMANA_DEGREE));
+        }
+        if self.build_list == 0 || self.build_list > MAX_VAMANA_BUILD_LIST {
+            return Err(format!("build_list must be between 1 and {}", MAX_VAMANA_BUILD_LIST));
+        }
+        if !(self.alpha >= 1.0 && self.alpha.is_finite()) {
+            return Err("alpha must be a finite number of at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Tamaño del índice, para el endpoint de estadísticas.
+#[derive(Debug, Serialize)]
+pub struct VamanaStats {
+    pub nodes: usize,
+    pub pending: usize, // Vectores insertados o actualizados después de construir el grafo.
+    pub stale: usize,   // Nodos de vectores borrados o actualizados.
+    pub degree: usize,
+    pub subquantizers: usize,
+    pub graph_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VamanaIndex {
+    distance_method: DistanceMethod,
+    params: VamanaParams,
+    graph_path: String,
+    dimension: usize,
+    record_size: usize,
+    medoid: u32,
+    ids: Vec<u64>,              // Id del vector de cada nodo.
+    nodes: HashMap<u64, u32>,   // Nodo de cada id.
+    codec: PqCodec,
+    codes: Vec<u8>,             // Códigos PQ de todos los nodos, uno detrás de otro.
+    stale: HashSet<u64>,        // Ids cuyo nodo ya no es la versión vigente.
+    pending: BTreeMap<u64, VFSVector>, // Versión vigente de los vectores que no están en el grafo.
+}
+
+impl VamanaIndex {
+    /// Construye el grafo con `vectors`, lo escribe en `graph_path` y se queda con los códigos PQ.
+    pub fn build(distance_method: DistanceMethod, vectors: &[VFSVector], params: &VamanaParams, graph_path: &str) -> io::Result<Self> {
+        let ids: Vec<u64> = vectors.iter().map(VFSVector::id).collect();
+        let values: Vec<Vec<f32>> = vectors.iter().map(|v| v.as_f32_vec()).collect();
+        let points: Vec<Vec<f32>> = values.iter().map(|v| prepare(&distance_method, v.clone())).collect();
+        let dimension = values.first().map_or(0, Vec::len);
+
+        let (graph, medoid) = build_graph(&points, params);
+        let record_size = record_size(params.degree, dimension);
+        write_graph(graph_path, dimension, params.degree, record_size, &ids, &graph, &values)?;
+
+        let m = params.pq_m.unwrap_or_else(|| default_subquantizers(dimension.max(1)));
+        let codec = PqCodec::train(&points, m);
+        let codes = points.iter().flat_map(|p| codec.encode(p.into())).collect();
+
+        Ok(Self {
+            distance_method,
+            params: *params,
+            graph_path: graph_path.to_string(),
+            dimension,
+            record_size,
+            medoid,
+            nodes: ids.iter().enumerate().map(|(node, &id)| (id, node as u32)).collect(),
+            ids,
+            codec,
+            codes,
+            stale: HashSet::new(),
+            pending: BTreeMap::new(),
+        })
+    }
+
+    pub fn distance_method(&self) -> DistanceMethod {
+        self.distance_method
+    }
+
+    pub fn params(&self) -> &VamanaParams {
+        &self.params
+    }
+
+    // Si el archivo del grafo sigue existiendo con el tamaño esperado.
+    pub fn graph_exists(&self) -> bool {
+        std::fs::metadata(&self.graph_path).is_ok_and(|m| m.len() == graph_len(self.record_size, self.ids.len()))
+    }
+
+    // Vectores vigentes: los nodos que no se han quedado antiguos más los pendientes.
+    pub fn len(&self) -> usize {
+        self.ids.len() - self.stale.len() + self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Hay que reconstruirlo si se construyó sin vectores o si han cambiado demasiados desde entonces.
+    pub fn is_outdated(&self) -> bool {
+        if self.ids.is_empty() {
+            return true;
+        }
+        let changed = self.stale.len().max(self.pending.len());
+        changed as f64 > MAX_OUTDATED_FRACTION * self.ids.len() as f64
+    }
+
+    pub fn stats(&self) -> VamanaStats {
+        VamanaStats {
+            nodes: self.ids.len(),
+            pending: self.pending.len(),
+            stale: self.stale.len(),
+            degree: self.params.degree,
+            subquantizers: self.codec.subquantizers(),
+            graph_bytes: graph_len(self.record_size, self.ids.len()),
+        }
+    }
+
+    // Los vectores nuevos o actualizados no se añaden al grafo: quedan pendientes hasta la próxima reconstrucción.
+    pub fn insert(&mut self, vector: &VFSVector) {
+        if self.nodes.contains_key(&vector.id()) {
+            self.stale.insert(vector.id());
+        }
+        self.pending.insert(vector.id(), vector.clone());
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        if self.nodes.contains_key(&id) {
+            self.stale.insert(id);
+        }
+        self.pending.remove(&id);
+    }
+
+    /// Búsqueda voraz guiada por las distancias PQ con una lista de `search_list` candidatos.
+    /// Cada nodo expandido se lee del disco y se mide con su vector completo. Los vectores pendientes se miden todos.
+    pub fn query(&self, query: &VFSVector, k: usize, search_list: usize) -> io::Result<Vec<(u64, f32)>> {
+        let mut results: Vec<(u64, f32)> = self.pending.iter()
+            .map(|(&id, vector)| (id, view_distance(&self.distance_method, query.view(), vector.view())))
+            .collect();
+
+        if !self.ids.is_empty() {
+            let normalized = normalized(&self.distance_method, query.view());
+            let table = self.codec.query_table(&self.distance_method, normalized.as_deref().map_or(query.view(), VectorView::from));
+            let pq_distance = |node: u32| {
+                let m = self.codec.subquantizers();
+                PqCodec::table_distance(&table, &self.codes[node as usize * m..(node as usize + 1) * m])
+            };
+
+            let mut file = File::open(&self.graph_path)?;
+            let mut record = vec![0u8; self.record_size];
+            let mut list: Vec<(f32, u32)> = vec![(pq_distance(self.medoid), self.medoid)];
+            let mut seen: HashSet<u32> = HashSet::from([self.medoid]);
+            let mut expanded: HashSet<u32> = HashSet::new();
+            while let Some(&(_, node)) = list.iter().find(|(_, node)| !expanded.contains(node)) {
+                expanded.insert(node);
+                file.seek(SeekFrom::Start(record_offset(self.record_size, node as usize)))?;
+                file.read_exact(&mut record)?;
+                let (id, neighbors, vector) = parse_record(&record, self.params.degree, self.dimension);
+
+                if !self.stale.contains(&id) {
+                    results.push((id, slice_distance(&self.distance_method, query.view(), &vector)));
+                }
+                for neighbor in neighbors {
+                    if seen.insert(neighbor) {
+                        list.push((pq_distance(neighbor), neighbor));
+                    }
+                }
+                list.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                list.truncate(search_list);
+            }
+        }
+
+        results.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(k);
+        Ok(results)
+    }
+}
+
+// Construcción del grafo en memoria (distancia euclídea entre los vectores ya preparados).
+// Devuelve la lista de vecinos de cada nodo y el medoide, que es el punto de entrada de las búsquedas.
+fn build_graph(points: &[Vec<f32>], params: &VamanaParams) -> (Vec<Vec<u32>>, u32) {
+    let n = points.len();
+    if n == 0 {
+        return (Vec::new(), 0);
+    }
+    let degree = params.degree.min(n - 1);
+    let mut rng = SmallRng::seed_from_u64(VAMANA_SEED);
+    let medoid = medoid(points);
+
+    // Se empieza con un grafo aleatorio de `degree` vecinos por nodo.
+    let mut graph: Vec<Vec<u32>> = (0..n)
+        .map(|node| {
+            let mut neighbors = Vec::with_capacity(degree);
+            while neighbors.len() < degree {
+                let candidate = rng.random_range(0..n) as u32;
+                if candidate as usize != node && !neighbors.contains(&candidate) {
+                    neighbors.push(candidate);
+                }
+            }
+            neighbors
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..n).collect();
+    for alpha in [1.0, params.alpha] {
+        order.shuffle(&mut rng);
+        for &node in &order {
+            let mut candidates = greedy_search(points, &graph, medoid, &points[node], params.build_list);
+            candidates.extend(&graph[node]);
+            graph[node] = robust_prune(points, node, candidates, alpha, degree);
+
+            // Enlaces de vuelta. Si el vecino ya tiene la lista llena, se vuelve a podar con el nodo nuevo.
+            for neighbor in graph[node].clone() {
+                let neighbor = neighbor as usize;
+                if graph[neighbor].contains(&(node as u32)) {
+                    continue;
+                }
+                if graph[neighbor].len() < degree {
+                    graph[neighbor].push(node as u32);
+                } else {
+                    let mut candidates = graph[neighbor].clone();
+                    candidates.push(node as u32);
+                    graph[neighbor] = robust_prune(points, neighbor, candidates, alpha, degree);
+                }
+            }
+        }
+    }
+    (graph, medoid)
+}
+
+// Punto más cercano a la media de todos.
+fn medoid(points: &[Vec<f32>]) -> u32 {
+    let mut mean = vec![0.0f32; points[0].len()];
+    for p in points {
+        for (m, x) in mean.iter_mut().zip(p) {
+            *m += x / points.len() as f32;
+        }
+    }
+    points.iter()
+        .map(|p| l2(p, &mean))
+        .enumerate()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map_or(0, |(node, _)| node as u32)
+}
+
+// Búsqueda voraz desde `start` con una lista de `list_size` candidatos. Devuelve los nodos expandidos.
+fn greedy_search(points: &[Vec<f32>], graph: &[Vec<u32>], start: u32, query: &[f32], list_size: usize) -> Vec<u32> {
+    let mut list: Vec<(f32, u32)> = vec![(l2(query, &points[start as usize]), start)];
+    let mut seen: HashSet<u32> = HashSet::from([start]);
+    let mut expanded: Vec<u32> = Vec::new();
+    while let Some(&(_, node)) = list.iter().find(|(_, node)| !expanded.contains(node)) {
+        expanded.push(node);
+        for &neighbor in &graph[node as usize] {
+            if seen.insert(neighbor) {
+                list.push((l2(query, &points[neighbor as usize]), neighbor));
+            }
+        }
+        list.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        list.truncate(list_size);
+    }
+    expanded
+}
+
+// Robust prune: se recorren los candidatos de más cercano a más lejano y cada uno elegido descarta a los
+// candidatos que están `alpha` veces más cerca de él que de `node`.
+fn robust_prune(points: &[Vec<f32>], node: usize, candidates: Vec<u32>, alpha: f32, degree: usize) -> Vec<u32> {
+    let mut unique = HashSet::new();
+    let mut candidates: Vec<(f32, u32)> = candidates.into_iter()
+        .filter(|&c| c as usize != node && unique.insert(c))
+        .map(|c| (l2(&points[node], &points[c as usize]), c))
+        .collect();
+    candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut neighbors = Vec::with_capacity(degree);
+    while !candidates.is_empty() && neighbors.len() < degree {
+        let (_, best) = candidates.remove(0);
+        neighbors.push(best);
+        candidates.retain(|&(distance, c)| alpha * l2(&points[best as usize], &points[c as usize]) > distance);
+    }
+    neighbors
+}
+
+fn l2(a: &[f32], b: &[f32]) -> f32 {
+    slice_distance(&DistanceMethod::Euclidean, a.into(), b)
+}
+
+// Registro de un nodo: id (u64), número de vecinos (u32), `degree` vecinos (u32) y el vector (`dimension` f32), en little-endian.
+fn record_size(degree: usize, dimension: usize) -> usize {
+    8 + 4 + 4 * degree + 4 * dimension
+}
+
+// Registros que caben en un bloque (0 si un registro necesita varios bloques).
+fn records_per_block(record_size: usize) -> usize {
+    BLOCK_SIZE / record_size
+}
+
+fn blocks_per_record(record_size: usize) -> usize {
+    record_size.div_ceil(BLOCK_SIZE)
+}
+
+// El bloque 0 es la cabecera. Si caben varios registros por bloque, ninguno cruza el límite de un bloque;
+// si no, cada registro empieza en un bloque nuevo.
+fn record_offset(record_size: usize, node: usize) -> u64 {
+    let per_block = records_per_block(record_size);
+    let offset = match node.checked_div(per_block) {
+        Some(block) => BLOCK_SIZE * (1 + block) + (node % per_block) * record_size,
+        None => BLOCK_SIZE * (1 + node * blocks_per_record(record_size)),
+    };
+    offset as u64
+}
+
+// Tamaño del archivo con `nodes` registros: la cabecera y los bloques que ocupan.
+fn graph_len(record_size: usize, nodes: usize) -> u64 {
+    let end = if nodes == 0 { BLOCK_SIZE as u64 } else { record_offset(record_size, nodes - 1) + record_size as u64 };
+    end.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64
+}
+
+// Escribe el grafo en un archivo temporal y lo renombra, para no dejar un grafo a medias si el proceso se cae.
+fn write_graph(path: &str, dimension: usize, degree: usize, record_size: usize, ids: &[u64], graph: &[Vec<u32>], values: &[Vec<f32>]) -> io::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+    let mut writer = BufWriter::new(file);
+
+    let mut header = Vec::with_capacity(BLOCK_SIZE);
+    header.extend_from_slice(GRAPH_MAGIC);
+    header.extend_from_slice(&(dimension as u32).to_le_bytes());
+    header.extend_from_slice(&(degree as u32).to_le_bytes());
+    header.extend_from_slice(&(ids.len() as u64).to_le_bytes());
+    header.extend_from_slice(&(record_size as u32).to_le_bytes());
+    header.resize(BLOCK_SIZE, 0);
+    writer.write_all(&header)?;
+
+    let mut position = BLOCK_SIZE as u64;
+    let mut record = Vec::with_capacity(record_size);
+    for (node, ((id, neighbors), vector)) in ids.iter().zip(graph).zip(values).enumerate() {
+        let offset = record_offset(record_size, node);
+        writer.write_all(&vec![0u8; (offset - position) as usize])?;
+
+        record.clear();
+        record.extend_from_slice(&id.to_le_bytes());
+        record.extend_from_slice(&(neighbors.len() as u32).to_le_bytes());
+        for slot in 0..degree {
+            record.extend_from_slice(&neighbors.get(slot).copied().unwrap_or(NO_NEIGHBOR).to_le_bytes());
+        }
+        for x in vector {
+            record.extend_from_slice(&x.to_le_bytes());
+        }
+        writer.write_all(&record)?;
+        position = offset + record_size as u64;
+    }
+    // Completar el último bloque.
+    writer.write_all(&vec![0u8; (graph_len(record_size, ids.len()) - position) as usize])?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+fn parse_record(record: &[u8], degree: usize, dimension: usize) -> (u64, Vec<u32>, Vec<f32>) {
+    let u32_at = |offset: usize| u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap());
+    let id = u64::from_le_bytes(record[0..8].try_into().unwrap());
+    let count = (u32_at(8) as usize).min(degree);
+    let neighbors = (0..count).map(|slot| u32_at(12 + 4 * slot)).collect();
+    let values_start = 12 + 4 * degree;
+    let values = (0..dimension).map(|i| f32::from_bits(u32_at(values_start + 4 * i))).collect();
+    (id, neighbors, values)
+}
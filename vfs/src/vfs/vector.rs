@@ -3,6 +3,7 @@ use serde::{Serialize, Deserialize};
 use core::simd::Simd;
 use std::simd::{SupportedLaneCount, LaneCount};
 use std::convert::TryInto;
+use std::ops::Range;
 use chrono::{DateTime, Utc};
 
 use super::err::VFSError;
@@ -42,6 +43,14 @@ pub enum VFSVector {
     Quantized(QuantizedVector),
 }
 
+// Valores de un vector prestados, sin copiarlos. Los cuantizados se descuantizan al leer cada valor.
+// Las funciones de distancia trabajan sobre vistas para no reservar memoria en cada comparación.
+#[derive(Debug, Clone, Copy)]
+pub enum VectorView<'a> {
+    Dense(&'a [f32]),
+    Quantized(&'a [i8], f32), // Valores y factor de escala.
+}
+
 impl Vector {
     // Constructor vector normal.
     pub fn from_vec(vector: Vec<f32>, id: u64, name: &str, tags: Vec<String>) -> Self {
@@ -289,6 +298,14 @@ impl VFSVector {
         }
     }
     
+    // Vista de los valores sin copiarlos. Es lo que debe usarse para calcular distancias.
+    pub fn view(&self) -> VectorView<'_> {
+        match self {
+           VFSVector::Dense(v) => VectorView::Dense(&v.vector),
+           VFSVector::Quantized(qv) => VectorView::Quantized(&qv.vector, qv.scale_factor),
+        }
+    }
+
     // Método para obtener los valores como Vec<i8> (cuantizando si es necesario)
     pub fn as_i8_vec(&self, scale_factor: Option<f32>) -> Vec<i8> {
         match self {
@@ -308,3 +325,45 @@ impl VFSVector {
 
 
 
+impl<'a> VectorView<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            VectorView::Dense(values) => values.len(),
+            VectorView::Quantized(values, _) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Valor en la posición `i`, descuantizado si hace falta.
+    #[inline]
+    pub fn get(&self, i: usize) -> f32 {
+        match self {
+            VectorView::Dense(values) => values[i],
+            VectorView::Quantized(values, scale_factor) => (values[i] as f32) / scale_factor,
+        }
+    }
+
+    // Vista de los valores de `range`, por ejemplo un subvector de PQ.
+    pub fn slice(&self, range: Range<usize>) -> VectorView<'a> {
+        match *self {
+            VectorView::Dense(values) => VectorView::Dense(&values[range]),
+            VectorView::Quantized(values, scale_factor) => VectorView::Quantized(&values[range], scale_factor),
+        }
+    }
+}
+
+// Los centroides y subvectores de los índices son listas de f32.
+impl<'a> From<&'a [f32]> for VectorView<'a> {
+    fn from(values: &'a [f32]) -> Self {
+        VectorView::Dense(values)
+    }
+}
+
+impl<'a> From<&'a Vec<f32>> for VectorView<'a> {
+    fn from(values: &'a Vec<f32>) -> Self {
+        VectorView::Dense(values)
+    }
+}
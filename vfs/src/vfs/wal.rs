@@ -0,0 +1,208 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use serde::{Serialize, Deserialize};
+use bincode;
+use super::vector::VFSVector;
+use super::err::VFSError;
+use super::ann::{HnswParams, LshParams};
+use super::ivf::IvfParams;
+use super::vamana::VamanaParams;
+
+// Write-ahead log (WAL) de la memtable.
+// Los vectores viven en la memtable hasta que se alcanza el umbral de flush, así que un crash perdería
+// las inserciones ya confirmadas al cliente. Cada operación se añade al WAL antes de aplicarse en memoria
+// y al arrancar el servidor se reproduce el log para reconstruir la memtable y el contador de ids.
+//
+// Formato del archivo:
+// - Una cabecera (`WalHeader`) con la configuración del manager, necesaria para recrearlo al arrancar.
+// - Una secuencia de entradas (`WalEntry`).
+// Cada bloque se guarda como [tamaño: u64 little endian][datos: bincode].
+// Una entrada incompleta al final del archivo (escritura interrumpida) se descarta al leer.
+
+const LEN_SIZE: usize = std::mem::size_of::<u64>();
+
+// Cuándo se fuerza la escritura del WAL a disco (fsync).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum DurabilityMode {
+    Always,         // fsync tras cada entrada. Ninguna escritura confirmada se pierde.
+    Batched(usize), // fsync cada N entradas. Se pueden perder hasta N - 1 entradas si se cae la máquina.
+    Os,             // El sistema operativo decide. Sobrevive a la caída del proceso, pero no a la de la máquina.
+}
+
+impl DurabilityMode {
+    pub fn parse(mode: &str, batch_size: Option<usize>) -> Option<Self> {
+        match mode {
+            "always" => Some(DurabilityMode::Always),
+            "batched" => Some(DurabilityMode::Batched(batch_size.unwrap_or(10).max(1))),
+            "os" => Some(DurabilityMode::Os),
+            _ => None,
+        }
+    }
+}
+
+// Configuración con la que se creó el manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalHeader {
+    pub name: String,
+    pub vector_dimension: usize,
+    pub quantize: bool,
+    pub durability: DurabilityMode,
+    pub mmap: bool,
+    pub cache_bytes: usize,
+    pub distance_method: Option<String>, // Distancia por defecto de las búsquedas en la colección.
+    pub minkowski_p: Option<f32>, // Exponente de la distancia por defecto si es "minkowski".
+    pub hnsw: HnswParams, // Parámetros del grafo HNSW de la colección.
+    pub hnsw_threads: usize, // Hilos para reconstruir el grafo desde el archivo de datos.
+    pub ivf: IvfParams, // Parámetros del índice IVF de la colección.
+    pub lsh: LshParams, // Parámetros del índice LSH de la colección.
+    pub vamana: VamanaParams, // Parámetros del grafo Vamana de la colección.
+}
+
+// Operaciones registradas en el WAL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalEntry {
+    Insert(VFSVector),           // Nuevo vector en la memtable.
+    Upsert(VFSVector),           // Nueva versión de un vector (ya escrita en el archivo de datos).
+    Delete(u64),                 // Vector borrado (su lápida ya está en el archivo de datos).
+    Checkpoint { next_id: u64 }, // Siguiente id a asignar en el momento en el que se reescribió el log.
+}
+
+pub struct Wal {
+    path: String,
+    file: File,
+    header: WalHeader,
+    unsynced: usize, // Entradas escritas desde el último fsync.
+}
+
+impl Wal {
+    // Crea un WAL vacío (truncando el anterior si existía) con la cabecera indicada.
+    pub fn create(path: &str, header: WalHeader) -> Result<Self, VFSError> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        write_frame(&mut file, &header)?;
+        file.sync_all()?;
+
+        Ok(Self { path: path.to_string(), file, header, unsynced: 0 })
+    }
+
+    // Lee un WAL existente. Devuelve `None` si no existe o está vacío.
+    // Si la última entrada está incompleta se trunca el archivo para poder seguir escribiendo a continuación.
+    pub fn read(path: &str) -> Result<Option<(WalHeader, Vec<WalEntry>)>, VFSError> {
+        let mut buffer = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => { file.read_to_end(&mut buffer)?; },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(VFSError::IoError(e)),
+        }
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let (header, mut cursor) = match read_frame::<WalHeader>(&buffer, 0) {
+            Some(frame) => frame,
+            None => return Err(VFSError::SerializationError(format!("Invalid WAL header in {}", path))),
+        };
+
+        let mut entries = Vec::new();
+        while let Some((entry, next)) = read_frame::<WalEntry>(&buffer, cursor) {
+            entries.push(entry);
+            cursor = next;
+        }
+
+        if cursor < buffer.len() {
+            println!("WAL: descartando {} bytes de una entrada incompleta", buffer.len() - cursor);
+            OpenOptions::new().write(true).open(path)?.set_len(cursor as u64)?;
+        }
+
+        Ok(Some((header, entries)))
+    }
+
+    // Abre un WAL existente para seguir añadiendo entradas.
+    pub fn open(path: &str, header: WalHeader) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self { path: path.to_string(), file, header, unsynced: 0 })
+    }
+
+    pub fn header(&self) -> &WalHeader {
+        &self.header
+    }
+
+    // Añade una entrada al log y hace fsync según el modo de durabilidad.
+    pub fn append(&mut self, entry: &WalEntry) -> Result<(), VFSError> {
+        write_frame(&mut self.file, entry)?;
+        self.unsynced += 1;
+
+        let sync = match self.header.durability {
+            DurabilityMode::Always => true,
+            DurabilityMode::Batched(n) => self.unsynced >= n,
+            DurabilityMode::Os => false,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    // Reescribe el log con la cabecera y las entradas indicadas.
+    // Se usa tras volcar la memtable a disco: solo hace falta conservar lo que sigue en memoria.
+    // Se escribe en un archivo temporal que después sustituye al WAL, para no perder el log si se cae a mitad.
+    pub fn rewrite(&mut self, entries: &[WalEntry]) -> Result<(), VFSError> {
+        let tmp_path = format!("{}.tmp", self.path);
+        {
+            let mut tmp = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            write_frame(&mut tmp, &self.header)?;
+            for entry in entries {
+                write_frame(&mut tmp, entry)?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+fn write_frame<T: Serialize>(file: &mut File, value: &T) -> Result<(), VFSError> {
+    let bytes = bincode::serialize(value).map_err(|e| VFSError::SerializationError(e.to_string()))?;
+    let mut frame = Vec::with_capacity(LEN_SIZE + bytes.len());
+    frame.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&bytes);
+    // Una sola escritura por entrada para reducir la probabilidad de entradas a medias.
+    file.write_all(&frame)?;
+    Ok(())
+}
+
+// Lee el bloque que empieza en `cursor`. Devuelve el valor y la posición del siguiente bloque,
+// o `None` si el bloque está incompleto o no puede deserializarse.
+fn read_frame<T: for<'de> Deserialize<'de>>(buffer: &[u8], cursor: usize) -> Option<(T, usize)> {
+    if cursor + LEN_SIZE > buffer.len() {
+        return None;
+    }
+    let len = u64::from_le_bytes(buffer[cursor..cursor + LEN_SIZE].try_into().ok()?) as usize;
+    let start = cursor + LEN_SIZE;
+    let end = start.checked_add(len)?;
+    if end > buffer.len() {
+        return None;
+    }
+    let value = bincode::deserialize(&buffer[start..end]).ok()?;
+    Some((value, end))
+}